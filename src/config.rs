@@ -0,0 +1,124 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use crate::{
+    eww::EwwConfig,
+    modules::{command::CommandConfig, composite::CompositeConfig, mpd},
+};
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// pfui configuration, read from `$XDG_CONFIG_HOME/pfui/config.toml` unless given with `--config`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// modules started by `pfui daemon`, written the same way as for `pfui start`, e.g. "hyprland workspace"
+    pub modules: Vec<String>,
+    /// push module output into eww variables
+    pub eww: Option<EwwConfig>,
+    /// `[serve]` settings of `pfui daemon --serve`
+    #[cfg(feature = "serve")]
+    pub serve: crate::server::ServeConfig,
+    /// kept without the serve feature, so the same config works with every build
+    #[cfg(not(feature = "serve"))]
+    serve: Option<toml::Value>,
+    /// `[mpd]` settings, flags of `pfui start mpd` take precedence
+    pub mpd: mpd::Opts,
+    /// `[mpris]` settings, flags of `pfui start mpris` take precedence
+    #[cfg(feature = "mpris")]
+    pub mpris: crate::modules::mpris::Opts,
+    /// kept without the mpris feature, so the same config works with every build
+    #[cfg(not(feature = "mpris"))]
+    mpris: Option<toml::Value>,
+    /// `[[command]]` modules, started with `pfui start command <name>`
+    #[serde(rename = "command")]
+    pub commands: Vec<CommandConfig>,
+    /// `[[composite]]` modules, started with `pfui start composite <name>`
+    #[serde(rename = "composite")]
+    pub composites: Vec<CompositeConfig>,
+    /// `[plugins.<name>]` settings, handed to the plugin as json
+    pub plugins: HashMap<String, toml::Value>,
+}
+
+/// `$<var>/pfui`, or `$HOME/<fallback>/pfui` if `var` isn't set, e.g. `xdg_dir("XDG_CONFIG_HOME", ".config")`
+pub fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback)))
+        .map(|dir| dir.join("pfui"))
+}
+
+/// default location of the config file
+fn default_path() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config").map(|dir| dir.join("config.toml"))
+}
+
+/// Loads the config file, this has to be called once before [`get`].
+/// An explicitly given path must exist, the default one is optional.
+pub fn load(path: Option<&Path>) -> Result<()> {
+    let config = match path {
+        Some(path) => parse(path)?,
+        None => match default_path() {
+            Some(path) if path.exists() => parse(&path)?,
+            _ => Config::default(),
+        },
+    };
+    // only the first load wins, there is nothing to update afterwards
+    let _ = CONFIG.set(config);
+    Ok(())
+}
+
+fn parse(path: &Path) -> Result<Config> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config {}", path.display()))?;
+    let config: Config =
+        toml::from_str(&content).with_context(|| format!("Invalid config {}", path.display()))?;
+    config.warn_disabled();
+    Ok(config)
+}
+
+impl Config {
+    /// Warns about sections of features this build doesn't have, they are ignored
+    fn warn_disabled(&self) {
+        let disabled: &[(&str, bool)] = &[
+            #[cfg(not(feature = "serve"))]
+            ("serve", self.serve.is_some()),
+            #[cfg(not(feature = "mpris"))]
+            ("mpris", self.mpris.is_some()),
+        ];
+        for (feature, _) in disabled.iter().filter(|(_, set)| *set) {
+            log::warn!("Ignoring [{feature}], pfui was built without the {feature} feature");
+        }
+    }
+}
+
+/// Returns the loaded config, or the default one if nothing was loaded
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sections_of_every_feature() {
+        let config: Config = toml::from_str(
+            r#"
+            modules = ["mpd"]
+            [serve]
+            origins = ["http://localhost:8080"]
+            [mpris]
+            priority = ["spotify"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.modules, ["mpd"]);
+        assert!(toml::from_str::<Config>("[no-such-section]").is_err());
+    }
+}
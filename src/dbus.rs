@@ -0,0 +1,208 @@
+//! `org.pfui` service on the session bus, every module is an object at `/org/pfui/<module>`
+//! with its output as `Json` property and an `Action` method for `pfui ctl` actions.
+use anyhow::Result;
+use clap::Parser;
+use log::warn;
+use serde_json::Value;
+use std::{
+    collections::HashSet,
+    sync::mpsc::{channel, Sender},
+};
+use zbus::{
+    blocking::{connection, Connection},
+    fdo, interface,
+};
+
+use crate::hub::{self, Sink};
+
+pub const SERVICE: &str = "org.pfui";
+
+struct Module {
+    name: String,
+    json: String,
+}
+
+#[interface(name = "org.pfui.Module")]
+impl Module {
+    /// name of the module, as used by `pfui start`
+    #[zbus(property)]
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// last output of the module, PropertiesChanged is emitted for every output
+    #[zbus(property)]
+    fn json(&self) -> String {
+        self.json.clone()
+    }
+
+    /// Runs a module action, `args` are the same as for `pfui ctl <module>`, e.g. `["next"]`
+    fn action(&self, args: Vec<String>) -> fdo::Result<()> {
+        let args = ["ctl", self.name.as_str()]
+            .into_iter()
+            .chain(args.iter().map(String::as_str));
+        let ctl =
+            crate::Ctl::try_parse_from(args).map_err(|e| fdo::Error::InvalidArgs(e.to_string()))?;
+        ctl.module
+            .run()
+            .map_err(|e| fdo::Error::Failed(format!("{e:#}")))
+    }
+}
+
+struct DBus {
+    conn: Connection,
+    /// modules which already have an object
+    objects: HashSet<String>,
+}
+
+/// Hands outputs to the thread updating the objects, bus calls can block
+struct Updates {
+    tx: Sender<(String, String)>,
+}
+
+/// object path of a module, only `[A-Za-z0-9_]` are allowed in path elements
+fn path(module: &str) -> String {
+    let name: String = module
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("/org/pfui/{name}")
+}
+
+impl DBus {
+    /// Connects with `builder`, taking the [`SERVICE`] name. The object manager at `/org/pfui`
+    /// announces modules as they show up, and being served from the start it gets the object
+    /// server dispatching before the first call arrives.
+    fn new(builder: connection::Builder) -> zbus::Result<Self> {
+        let conn = builder
+            .serve_at("/org/pfui", fdo::ObjectManager)?
+            .name(SERVICE)?
+            .build()?;
+        Ok(Self {
+            conn,
+            objects: HashSet::new(),
+        })
+    }
+
+    fn update(&mut self, module: &str, json: String) -> zbus::Result<()> {
+        let path = path(module);
+        if self.objects.insert(module.to_owned()) {
+            let object = Module {
+                name: module.to_owned(),
+                json,
+            };
+            self.conn.object_server().at(path, object)?;
+            return Ok(());
+        }
+        let iface = self
+            .conn
+            .object_server()
+            .interface::<_, Module>(path.as_str())?;
+        iface.get_mut().json = json;
+        let object = iface.get();
+        zbus::block_on(object.json_changed(iface.signal_context()))
+    }
+}
+
+impl Sink for Updates {
+    fn publish(&mut self, module: &str, output: &Value) {
+        if self
+            .tx
+            .send((module.to_owned(), output.to_string()))
+            .is_err()
+        {
+            warn!("dbus updater is not running, dropping output of {module}");
+        }
+    }
+}
+
+/// Connects to the session bus, takes the `org.pfui` name and publishes module outputs there
+pub fn start() -> Result<()> {
+    let mut dbus = DBus::new(connection::Builder::session()?)?;
+    let (tx, rx) = channel::<(String, String)>();
+    std::thread::spawn(move || {
+        for (module, json) in rx {
+            if let Err(e) = dbus.update(&module, json) {
+                warn!("Failed to update {module} on dbus: {e}");
+            }
+        }
+    });
+    hub::add_sink(Updates { tx });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, time::Duration};
+    use zbus::zvariant::OwnedValue;
+
+    #[test]
+    fn object_paths() {
+        assert_eq!(path("mpd"), "/org/pfui/mpd");
+        assert_eq!(path("hyprland-workspace"), "/org/pfui/hyprland_workspace");
+        assert_eq!(path("pulseaudio.privacy"), "/org/pfui/pulseaudio_privacy");
+    }
+
+    #[test]
+    fn actions_parse_like_ctl() {
+        let args = ["ctl", "mpd", "toggle"];
+        assert!(crate::Ctl::try_parse_from(args).is_ok());
+        let args = ["ctl", "mpd", "no-such-action"];
+        assert!(crate::Ctl::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn objects_on_private_bus() {
+        let Some((_bus, address)) = crate::testing::private_bus() else {
+            eprintln!("no dbus-daemon, skipping");
+            return;
+        };
+        let builder = || connection::Builder::address(address.as_str()).unwrap();
+        let mut dbus = DBus::new(builder()).unwrap();
+        let client = builder().build().unwrap();
+        let json = |module: &str| -> String {
+            let reply = client
+                .call_method(
+                    Some(SERVICE),
+                    path(module).as_str(),
+                    Some("org.freedesktop.DBus.Properties"),
+                    "Get",
+                    &("org.pfui.Module", "Json"),
+                )
+                .unwrap();
+            let value: zbus::zvariant::OwnedValue = reply.body().deserialize().unwrap();
+            String::try_from(value).unwrap()
+        };
+        dbus.update("mpd", String::from(r#"{"ok":0}"#)).unwrap();
+        assert_eq!(json("mpd"), r#"{"ok":0}"#);
+        // the object exists now, later updates are signaled
+        let rule = zbus::MatchRule::builder()
+            .msg_type(zbus::message::Type::Signal)
+            .interface("org.freedesktop.DBus.Properties")
+            .unwrap()
+            .member("PropertiesChanged")
+            .unwrap()
+            .path(path("mpd"))
+            .unwrap()
+            .build();
+        let signals = zbus::blocking::MessageIterator::for_match_rule(rule, &client, None).unwrap();
+        let (tx, rx) = channel();
+        std::thread::spawn(move || {
+            for signal in signals.flatten() {
+                let body = signal.body();
+                let (interface, changed, _): (String, HashMap<String, OwnedValue>, Vec<String>) =
+                    body.deserialize().unwrap();
+                if tx.send((interface, changed)).is_err() {
+                    return;
+                }
+            }
+        });
+        dbus.update("mpd", String::from(r#"{"ok":1}"#)).unwrap();
+        let (interface, changed) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(interface, "org.pfui.Module");
+        let value = changed.get("Json").unwrap().try_clone().unwrap();
+        assert_eq!(String::try_from(value).unwrap(), r#"{"ok":1}"#);
+        assert_eq!(json("mpd"), r#"{"ok":1}"#);
+    }
+}
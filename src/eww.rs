@@ -0,0 +1,328 @@
+//! Updates eww variables directly with `eww update`, so a single pfui process can feed any number
+//! of `defvar`s instead of a `deflisten` per module.
+use log::{debug, warn};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+    time::{Duration, Instant},
+};
+
+use crate::hub::Sink;
+
+/// `[eww]` section of the config
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EwwConfig {
+    /// eww executable, looked up in `PATH` if it's not a path
+    pub binary: PathBuf,
+    /// eww configuration directory, passed as `--config`
+    pub config: Option<PathBuf>,
+    /// milliseconds to collect changes after the first one before calling eww, so they go out in
+    /// one update
+    pub batch_ms: u64,
+    /// maps `<module>[.<field>.<field>...]` of the module output to an eww variable,
+    /// e.g. `"mpd.data.song.title" = "song_title"`, or just `"mpd" = "mpd"` for the whole output
+    pub vars: BTreeMap<String, String>,
+}
+
+impl Default for EwwConfig {
+    fn default() -> Self {
+        Self {
+            binary: PathBuf::from("eww"),
+            config: None,
+            batch_ms: 50,
+            vars: BTreeMap::new(),
+        }
+    }
+}
+
+/// single mapping of module field to eww variable
+#[derive(Debug, PartialEq)]
+struct Var {
+    module: String,
+    /// json pointer inside the module output
+    pointer: String,
+    name: String,
+}
+
+impl Var {
+    /// `path` is `<module>[.<field>.<field>...]`, like the keys of [`EwwConfig::vars`]
+    fn new(path: &str, name: &str) -> Self {
+        let (module, fields) = path.split_once('.').unwrap_or((path, ""));
+        let pointer = fields
+            .split('.')
+            .filter(|field| !field.is_empty())
+            .fold(String::new(), |pointer, field| pointer + "/" + field);
+        Self {
+            module: module.to_owned(),
+            pointer,
+            name: name.to_owned(),
+        }
+    }
+
+    /// The value of the variable in `output`, strings without quotes
+    fn value(&self, output: &Value) -> String {
+        // missing fields are cleared, e.g. data is null when the module is not ok
+        match output.pointer(&self.pointer) {
+            Some(Value::String(string)) => string.clone(),
+            Some(Value::Null) | None => String::new(),
+            Some(value) => value.to_string(),
+        }
+    }
+}
+
+/// how long to wait before sending updates again which eww didn't take, e.g. while it's not running
+const RETRY: Duration = Duration::from_secs(5);
+
+pub struct Eww {
+    vars: Vec<Var>,
+    tx: Sender<(String, String)>,
+}
+
+impl Eww {
+    /// Creates the sink and spawns the thread which calls eww
+    pub fn new(config: &EwwConfig) -> Self {
+        let vars = config
+            .vars
+            .iter()
+            .map(|(path, name)| Var::new(path, name))
+            .collect();
+        let (tx, rx) = channel();
+        let binary = config.binary.clone();
+        let eww_config = config.config.clone();
+        let batch = Duration::from_millis(config.batch_ms);
+        std::thread::spawn(move || {
+            updater(batch, RETRY, rx, |pending| {
+                update(&binary, eww_config.as_deref(), pending)
+            })
+        });
+        Self { vars, tx }
+    }
+}
+
+impl Sink for Eww {
+    fn publish(&mut self, module: &str, output: &Value) {
+        for var in self.vars.iter().filter(|var| var.module == module) {
+            if self.tx.send((var.name.clone(), var.value(output))).is_err() {
+                warn!(
+                    "eww updater is not running, dropping update of {}",
+                    var.name
+                );
+            }
+        }
+    }
+}
+
+/// Collects variable changes for `batch` from the first one and hands those which weren't sent
+/// yet to `update` at once. Changes `update` fails on are kept and tried again after `retry`.
+fn updater(
+    batch: Duration,
+    retry: Duration,
+    rx: Receiver<(String, String)>,
+    mut update: impl FnMut(&BTreeMap<String, String>) -> bool,
+) {
+    // last value eww took for a variable, unchanged values are not sent again
+    let mut sent: HashMap<String, String> = HashMap::new();
+    let mut pending: BTreeMap<String, String> = BTreeMap::new();
+    loop {
+        let first = if pending.is_empty() {
+            rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            rx.recv_timeout(retry)
+        };
+        match first {
+            Ok((name, value)) => {
+                pending.insert(name, value);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        // a deadline rather than a gap, so a module printing all the time still gets through
+        let deadline = Instant::now() + batch;
+        while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+            match rx.recv_timeout(left) {
+                Ok((name, value)) => {
+                    pending.insert(name, value);
+                }
+                // a disconnect is noticed by the next recv, send what we already have
+                Err(_) => break,
+            }
+        }
+        pending.retain(|name, value| sent.get(name) != Some(value));
+        if !pending.is_empty() && update(&pending) {
+            sent.extend(std::mem::take(&mut pending));
+        }
+    }
+}
+
+/// Runs `eww update` with `pending`, returns whether eww took it
+fn update(binary: &Path, eww_config: Option<&Path>, pending: &BTreeMap<String, String>) -> bool {
+    let args: Vec<String> = pending
+        .iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect();
+    debug!("eww update {args:?}");
+    let mut cmd = Command::new(binary);
+    if let Some(dir) = eww_config {
+        cmd.arg("--config").arg(dir);
+    }
+    match cmd.arg("update").args(&args).status() {
+        Ok(status) if status.success() => true,
+        Ok(status) => {
+            warn!("eww update exited with {status}, trying again in {RETRY:?}");
+            false
+        }
+        Err(e) => {
+            warn!("Failed to run eww: {e}, trying again in {RETRY:?}");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::{
+        sync::{Arc, Mutex},
+        thread::sleep,
+    };
+
+    #[test]
+    fn var_pointer() {
+        let var = Var::new("mpd.data.song.title", "song_title");
+        assert_eq!(var.module, "mpd");
+        assert_eq!(var.pointer, "/data/song/title");
+        assert_eq!(var.name, "song_title");
+        assert_eq!(Var::new("mpd", "mpd").pointer, "");
+    }
+
+    #[test]
+    fn var_value() {
+        let output = json!({"ok": 1, "data": {"title": "Song", "volume": 42, "art": null}});
+        assert_eq!(Var::new("mpd.data.title", "t").value(&output), "Song");
+        assert_eq!(Var::new("mpd.data.volume", "v").value(&output), "42");
+        assert_eq!(Var::new("mpd.data.art", "a").value(&output), "");
+        assert_eq!(Var::new("mpd.data.missing", "m").value(&output), "");
+        assert_eq!(Var::new("mpd", "mpd").value(&output), output.to_string());
+    }
+
+    type Calls = Arc<Mutex<Vec<BTreeMap<String, String>>>>;
+
+    /// Runs the updater on its own thread, recording the updates, `take` decides if eww takes one
+    fn spawn(
+        batch: u64,
+        retry: u64,
+        take: impl Fn(usize) -> bool + Send + 'static,
+    ) -> (Sender<(String, String)>, Calls, std::thread::JoinHandle<()>) {
+        let (tx, rx) = channel();
+        let calls = Calls::default();
+        let cclone = Arc::clone(&calls);
+        let handle = std::thread::spawn(move || {
+            updater(
+                Duration::from_millis(batch),
+                Duration::from_millis(retry),
+                rx,
+                |pending| {
+                    let mut calls = cclone.lock().unwrap();
+                    calls.push(pending.clone());
+                    take(calls.len())
+                },
+            )
+        });
+        (tx, calls, handle)
+    }
+
+    fn send(tx: &Sender<(String, String)>, name: &str, value: &str) {
+        tx.send((name.to_owned(), value.to_owned())).unwrap();
+    }
+
+    #[test]
+    fn batches_changes() {
+        let (tx, calls, handle) = spawn(100, 1000, |_| true);
+        send(&tx, "a", "1");
+        send(&tx, "b", "1");
+        send(&tx, "a", "2");
+        drop(tx);
+        handle.join().unwrap();
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].get("a").map(String::as_str), Some("2"));
+        assert_eq!(calls[0].get("b").map(String::as_str), Some("1"));
+    }
+
+    #[test]
+    fn constant_changes_get_through() {
+        let (tx, calls, handle) = spawn(50, 1000, |_| true);
+        for i in 0..60 {
+            send(&tx, "peak", &i.to_string());
+            sleep(Duration::from_millis(5));
+        }
+        let during = calls.lock().unwrap().len();
+        drop(tx);
+        handle.join().unwrap();
+        assert!(during >= 2, "only {during} updates while changing");
+    }
+
+    #[test]
+    fn unchanged_values_are_not_sent_again() {
+        let (tx, calls, handle) = spawn(10, 1000, |_| true);
+        send(&tx, "a", "1");
+        sleep(Duration::from_millis(50));
+        send(&tx, "a", "1");
+        sleep(Duration::from_millis(50));
+        send(&tx, "a", "2");
+        drop(tx);
+        handle.join().unwrap();
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[1].get("a").map(String::as_str), Some("2"));
+    }
+
+    #[test]
+    fn failed_updates_are_retried() {
+        // eww isn't running for the first update
+        let (tx, calls, handle) = spawn(10, 20, |call| call > 1);
+        send(&tx, "a", "1");
+        sleep(Duration::from_millis(150));
+        // eww took it now, so the same value isn't sent a third time
+        send(&tx, "a", "1");
+        sleep(Duration::from_millis(50));
+        drop(tx);
+        handle.join().unwrap();
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0], calls[1]);
+    }
+
+    /// Writes an executable script into a directory of its own
+    fn script(name: &str, body: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("pfui-eww-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("eww");
+        std::fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn fake_eww() {
+        let binary = script("ok", r#"printf '%s\n' "$@" > "$(dirname "$0")/args""#);
+        let pending = BTreeMap::from([
+            (String::from("a"), String::from("1")),
+            (String::from("b"), String::from("two words")),
+        ]);
+        assert!(update(&binary, Some(Path::new("/cfg")), &pending));
+        let args = std::fs::read_to_string(binary.with_file_name("args")).unwrap();
+        assert_eq!(args, "--config\n/cfg\nupdate\na=1\nb=two words\n");
+
+        let binary = script("failing", "exit 1");
+        assert!(!update(&binary, None, &pending));
+        assert!(!update(Path::new("/nonexistent/eww"), None, &pending));
+    }
+}
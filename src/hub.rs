@@ -0,0 +1,156 @@
+//! Every module output goes through here and is handed to the registered sinks (stdout, eww, ...)
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+/// Something which consumes module outputs
+pub trait Sink: Send {
+    /// called for every output of every module, `output` is the serialized [`crate::Output`].
+    /// It's called with the hub locked, which keeps the outputs in order, so it must not block:
+    /// sinks doing I/O hand the output to a thread of their own, like eww and dbus
+    fn publish(&mut self, module: &str, output: &Value);
+}
+
+/// Registered sink, to remove it again with [`remove_sink`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SinkId(usize);
+
+struct Hub {
+    /// last output of every module
+    state: HashMap<String, Value>,
+    sinks: Vec<(SinkId, Box<dyn Sink>)>,
+    /// id of the next sink
+    next: usize,
+    /// modules running in this process
+    running: HashSet<String>,
+}
+
+impl Hub {
+    fn add(&mut self, sink: Box<dyn Sink>) -> SinkId {
+        let id = SinkId(self.next);
+        self.next += 1;
+        self.sinks.push((id, sink));
+        id
+    }
+}
+
+static HUB: Mutex<Option<Hub>> = Mutex::new(None);
+
+fn with_hub<R>(f: impl FnOnce(&mut Hub) -> R) -> R {
+    let mut lock = HUB.lock().unwrap();
+    f(lock.get_or_insert_with(|| Hub {
+        state: HashMap::new(),
+        sinks: Vec::new(),
+        next: 0,
+        running: HashSet::new(),
+    }))
+}
+
+pub fn add_sink<S: Sink + 'static>(sink: S) -> SinkId {
+    with_hub(|hub| hub.add(Box::new(sink)))
+}
+
+/// Registers the sink returned by `f`, which gets the last output of every module first.
+/// Nothing is published in between, so the sink gets every output after those.
+pub fn add_sink_with<S: Sink + 'static>(f: impl FnOnce(&HashMap<String, Value>) -> S) -> SinkId {
+    with_hub(|hub| {
+        let sink = f(&hub.state);
+        hub.add(Box::new(sink))
+    })
+}
+
+pub fn remove_sink(id: SinkId) {
+    with_hub(|hub| hub.sinks.retain(|(sink, _)| *sink != id));
+}
+
+/// Marks `module` as running, returns false if it already is
+pub fn claim(module: &str) -> bool {
+    with_hub(|hub| hub.running.insert(module.to_owned()))
+}
+
+/// Hands `output` to all sinks, unless it's the same as the last output of `module`
+pub fn publish(module: &str, output: Value) {
+    with_hub(|hub| {
+        if hub.state.get(module) == Some(&output) {
+            return;
+        }
+        hub.sinks
+            .iter_mut()
+            .for_each(|(_, sink)| sink.publish(module, &output));
+        hub.state.insert(module.to_owned(), output);
+    });
+}
+
+/// Calls `f` with the last output of `module`, no output is published while `f` runs,
+/// so sinks can hand out the current state and then continue with the updates without missing one
+#[cfg(feature = "serve")]
+pub fn with_snapshot<R>(module: &str, f: impl FnOnce(Option<&Value>) -> R) -> R {
+    with_hub(|hub| f(hub.state.get(module)))
+}
+
+/// Prints output of a single module to stdout, this is what `deflisten` reads
+pub struct Stdout {
+    module: String,
+}
+
+impl Stdout {
+    pub fn new(module: impl Into<String>) -> Self {
+        Self {
+            module: module.into(),
+        }
+    }
+}
+
+impl Sink for Stdout {
+    fn publish(&mut self, module: &str, output: &Value) {
+        if module == self.module {
+            println!("{output}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::{
+        mpsc::{channel, Sender},
+        Arc,
+    };
+
+    struct Collect(Sender<(String, Value)>);
+
+    impl Sink for Collect {
+        fn publish(&mut self, module: &str, output: &Value) {
+            if module.starts_with("hub-test") {
+                let _ = self.0.send((module.to_owned(), output.clone()));
+            }
+        }
+    }
+
+    #[test]
+    fn snapshot_then_updates() {
+        publish("hub-test-a", json!(1));
+        let (tx, rx) = channel();
+        let snapshot = Arc::new(Mutex::new(None));
+        let sclone = Arc::clone(&snapshot);
+        let id = add_sink_with(|state| {
+            *sclone.lock().unwrap() = state.get("hub-test-a").cloned();
+            Collect(tx)
+        });
+        assert_eq!(*snapshot.lock().unwrap(), Some(json!(1)));
+        publish("hub-test-a", json!(2));
+        // the same output isn't published twice
+        publish("hub-test-a", json!(2));
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            (String::from("hub-test-a"), json!(2))
+        );
+        assert!(rx.try_recv().is_err());
+        remove_sink(id);
+        publish("hub-test-a", json!(3));
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -1,10 +1,23 @@
 use anyhow::Result;
-use std::process::exit;
+use std::{
+    process::exit,
+    thread::sleep,
+    time::{Duration, Instant},
+};
 
 use clap::{ColorChoice, Parser, Subcommand};
 
+mod config;
+#[cfg(feature = "dbus")]
+mod dbus;
+mod eww;
+mod hub;
 mod modules;
-use modules::{hyprland, mpd, pulseaudio, sway};
+#[cfg(feature = "serve")]
+mod server;
+#[cfg(all(test, any(feature = "dbus", feature = "mpris")))]
+mod testing;
+use modules::{command, hyprland, mpd, pulseaudio, sway};
 use serde::Serialize;
 
 use crate::modules::backlight;
@@ -28,11 +41,16 @@ struct Cli {
     /// output log file, logs will be written to this file, default will be written to stderr
     #[arg(short, long)]
     output: Option<std::path::PathBuf>,
+    /// config file, default is $XDG_CONFIG_HOME/pfui/config.toml
+    #[arg(short, long)]
+    config: Option<std::path::PathBuf>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     Start(Start),
+    Daemon(Daemon),
+    Ctl(Ctl),
 }
 
 #[derive(Parser)]
@@ -42,11 +60,59 @@ struct Start {
     pub module: Modules,
 }
 
+#[derive(Parser)]
+#[command(
+    about = "Run several modules in one process and push their output to eww and other outputs"
+)]
+struct Daemon {
+    /// modules to run, e.g. "mpd" "hyprland workspace", default is `modules` from the config
+    modules: Vec<String>,
+    /// serve module outputs over http, SSE and websocket on this address, e.g. 127.0.0.1:7878
+    #[cfg(feature = "serve")]
+    #[arg(long)]
+    serve: Option<std::net::SocketAddr>,
+    /// provide module outputs as org.pfui service on the session bus
+    #[cfg(feature = "dbus")]
+    #[arg(long)]
+    dbus: bool,
+}
+
+#[derive(Parser)]
+#[command(about = "Run an action of a module, e.g. `pfui ctl mpd toggle`")]
+struct Ctl {
+    #[command(subcommand)]
+    module: CtlModules,
+}
+
+#[derive(Subcommand)]
+enum CtlModules {
+    #[command(subcommand)]
+    Mpd(mpd::Action),
+    #[command(subcommand, name = "pulseaudio")]
+    PulseAudio(pulseaudio::Action),
+}
+
+impl CtlModules {
+    fn run(&self) -> anyhow::Result<()> {
+        match self {
+            CtlModules::Mpd(action) => action.run(),
+            CtlModules::PulseAudio(action) => action.run(),
+        }
+    }
+}
+
 #[derive(Subcommand)]
 enum Modules {
-    Mpd,
-    #[command(name = "pulseaudio")]
-    PulseAudio,
+    #[command(about = "monitors the music player daemon")]
+    Mpd(mpd::Opts),
+    #[cfg(feature = "mpris")]
+    #[command(about = "monitors media players on the session bus")]
+    Mpris(modules::mpris::Opts),
+    #[command(
+        name = "pulseaudio",
+        about = "monitors pulseaudio sinks, sources and streams"
+    )]
+    PulseAudio(pulseaudio::Opts),
     #[command(alias = "i3")]
     Sway,
     #[command(subcommand, about = "monitors hyprland  window,workspace etc events")]
@@ -55,6 +121,38 @@ enum Modules {
     Backlight,
     #[command(about = "monitors external disks insert/remove, mount/umount events")]
     Disks,
+    #[command(about = "runs a command module defined in the config")]
+    Command { name: String },
+    #[command(about = "runs a WebAssembly plugin from $XDG_DATA_HOME/pfui/plugins")]
+    Plugin { name: String },
+    #[command(about = "runs a composite module defined in the config")]
+    Composite { name: String },
+}
+
+impl Modules {
+    /// name under which the module output is published
+    fn name(&self) -> &str {
+        match self {
+            Modules::Mpd(_) => mpd::NAME,
+            #[cfg(feature = "mpris")]
+            Modules::Mpris(_) => modules::mpris::NAME,
+            Modules::PulseAudio(opts) => opts.name(),
+            Modules::Sway => sway::NAME,
+            Modules::Hyprland(opts) => opts.name(),
+            Modules::Backlight => backlight::NAME,
+            Modules::Disks => modules::disks::NAME,
+            Modules::Command { name } => name,
+            Modules::Plugin { name } => name,
+            Modules::Composite { name } => name,
+        }
+    }
+
+    /// Parses a module written like on the command line, e.g. "hyprland workspace"
+    fn from_spec(spec: &str) -> anyhow::Result<Self> {
+        Start::try_parse_from(std::iter::once("start").chain(spec.split_whitespace()))
+            .map(|start| start.module)
+            .map_err(|e| anyhow::anyhow!("Invalid module {spec:?}: {e}"))
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -76,8 +174,8 @@ trait Module {
     /// This generates the data and calls print
     fn output(&self, conn: &mut Self::Connection);
 }
-/// This actually prints the json representation of the data
-pub fn print<T: serde::Serialize>(info: &Option<T>) {
+/// This actually prints the json representation of the data, to every sink listening for `module`
+pub fn print<T: serde::Serialize>(module: &str, info: &Option<T>) {
     let output = if let Some(data) = info {
         Output {
             ok: 1,
@@ -86,7 +184,148 @@ pub fn print<T: serde::Serialize>(info: &Option<T>) {
     } else {
         Output { ok: 0, data: None }
     };
-    println!("{}", serde_json::to_string(&output).unwrap());
+    hub::publish(module, serde_json::to_value(&output).unwrap());
+}
+
+/// first delay before restarting a failed module, doubled on every failure
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+/// restart delay doesn't grow beyond this, a module running longer than this resets it
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Error which restarting the module won't fix, e.g. a wrong password
+#[derive(Debug)]
+pub struct Fatal(pub String);
+
+impl std::fmt::Display for Fatal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Fatal {}
+
+/// Calls `start` until it returns Ok, restarting it with exponential backoff when it fails,
+/// unless the error is [`Fatal`]. While the module is down its output is `ok: 0`.
+fn supervise(module: &str, mut start: impl FnMut() -> Result<()>) {
+    let mut delay = MIN_BACKOFF;
+    loop {
+        let started = Instant::now();
+        let Err(e) = start() else { return };
+        if e.is::<Fatal>() {
+            log::error!("{module} stopped: {e:#}");
+            print::<()>(module, &None);
+            return;
+        }
+        if started.elapsed() > MAX_BACKOFF {
+            delay = MIN_BACKOFF;
+        }
+        log::warn!("{module} failed: {e:#}, restarting in {delay:?}");
+        print::<()>(module, &None);
+        sleep(delay);
+        delay = (delay * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Runs the module until it finishes, restarting it on errors.
+/// Returns right away if the module is already running in this process.
+fn run(module: &Modules) {
+    let name = module.name();
+    if !hub::claim(name) {
+        return;
+    }
+    match module {
+        Modules::Mpd(opts) => {
+            if cfg!(feature = "mpd") {
+                supervise(name, || mpd::Mpd::new(opts).start(5));
+            } else {
+                println!("Feature not enabled");
+            }
+        }
+        #[cfg(feature = "mpris")]
+        Modules::Mpris(opts) => supervise(name, || modules::mpris::Mpris::new(opts).start()),
+        Modules::PulseAudio(opts) => {
+            if cfg!(feature = "pulseaudio") {
+                supervise(name, || pulseaudio::PulseAudio::new(opts).start(5));
+            } else {
+                println!("Feature not enabled");
+            }
+        }
+        Modules::Sway => {
+            if cfg!(feature = "sway") {
+                supervise(name, || sway::Sway {}.start(5));
+            } else {
+                println!("Feature not enabled");
+            }
+        }
+        Modules::Hyprland(ref opts) => {
+            if cfg!(feature = "hyprland") {
+                supervise(name, || hyprland::HyprlandListener::new(opts).listen());
+            } else {
+                println!("Feature not enabled");
+            }
+        }
+        Modules::Backlight => {
+            if cfg!(feature = "backlight") {
+                supervise(name, || backlight::Backlight::new()?.listen());
+            } else {
+                eprintln!("Feature not enabled");
+            }
+        }
+        Modules::Disks => {
+            if cfg!(feature = "disk") {
+                supervise(name, || modules::disks::DiskMon::new()?.listen());
+            } else {
+                eprintln!("Feature not enabled");
+            }
+        }
+        Modules::Command { name } => supervise(name, || command::Command::new(name)?.start()),
+        Modules::Plugin { name } => {
+            #[cfg(feature = "wasm")]
+            supervise(name, || modules::plugin::Plugin::new(name).start());
+            #[cfg(not(feature = "wasm"))]
+            eprintln!("Feature not enabled, can't run {name}");
+        }
+        Modules::Composite { name } => {
+            supervise(name, || modules::composite::Composite::new(name)?.start())
+        }
+    }
+}
+
+/// Starts every module in its own thread, output only goes to the configured sinks
+fn daemon(opts: &Daemon) -> anyhow::Result<()> {
+    let config = config::get();
+    let mut outputs = 0;
+    if let Some(eww) = &config.eww {
+        hub::add_sink(eww::Eww::new(eww));
+        outputs += 1;
+    }
+    #[cfg(feature = "serve")]
+    if let Some(addr) = opts.serve {
+        server::start(addr)?;
+        outputs += 1;
+    }
+    #[cfg(feature = "dbus")]
+    if opts.dbus {
+        dbus::start()?;
+        outputs += 1;
+    }
+    if outputs == 0 {
+        log::warn!("No outputs configured, module output goes nowhere");
+    }
+    let specs = if opts.modules.is_empty() {
+        &config.modules
+    } else {
+        &opts.modules
+    };
+    let mut handles = Vec::with_capacity(specs.len());
+    for spec in specs {
+        let module = Modules::from_spec(spec)?;
+        handles.push(std::thread::spawn(move || run(&module)));
+    }
+    for handle in handles {
+        let _ = handle.join();
+    }
+    Ok(())
 }
 
 fn main() {
@@ -118,56 +357,29 @@ fn main() {
     });
     builder.init();
 
+    if let Err(e) = config::load(cli.config.as_deref()) {
+        eprintln!("{e:?}");
+        exit(1);
+    }
+
     match &cli.command {
-        Some(Commands::Start(start)) => match start.module {
-            Modules::Mpd => {
-                if cfg!(feature = "mpd") {
-                    while let Err(..) = (mpd::Mpd {}.start(5)) {}
-                    exit(0);
-                } else {
-                    println!("Feature not enabled");
-                }
-            }
-            Modules::PulseAudio => {
-                if cfg!(feature = "pulseaudio") {
-                    while let Err(..) = (pulseaudio::PulseAudio {}.start(5)) {}
-                    exit(0);
-                } else {
-                    println!("Feature not enabled");
-                }
-            }
-            Modules::Sway => {
-                if cfg!(feature = "sway") {
-                    while let Err(..) = (sway::Sway {}.start(5)) {}
-                    exit(0);
-                } else {
-                    println!("Feature not enabled");
-                }
-            }
-            Modules::Hyprland(ref opts) => {
-                if cfg!(feature = "hyprland") {
-                    while let Err(..) = hyprland::HyprlandListener::new(opts).listen() {}
-                    exit(0);
-                } else {
-                    println!("Feature not enabled");
-                }
-            }
-            Modules::Backlight => {
-                if cfg!(feature = "backlight") {
-                    backlight::Backlight::new().listen().unwrap();
-                } else {
-                    eprintln!("Feature not enabled");
-                }
+        Some(Commands::Start(start)) => {
+            hub::add_sink(hub::Stdout::new(start.module.name()));
+            run(&start.module);
+            exit(0);
+        }
+        Some(Commands::Daemon(opts)) => {
+            if let Err(e) = daemon(opts) {
+                eprintln!("{e:?}");
+                exit(1);
             }
-            Modules::Disks => {
-                if cfg!(feature = "disk") {
-                    while modules::disks::DiskMon::new().listen().is_err() {}
-                    exit(0);
-                } else {
-                    eprintln!("Feature not enabled");
-                }
+        }
+        Some(Commands::Ctl(ctl)) => {
+            if let Err(e) = ctl.module.run() {
+                eprintln!("{e:?}");
+                exit(1);
             }
-        },
+        }
         None => {}
     }
 }
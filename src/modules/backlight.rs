@@ -1,11 +1,13 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
 use std::{
     fs::read_to_string,
     io::{Read, Seek, SeekFrom},
     path::PathBuf,
-    process::exit,
 };
+
+pub const NAME: &str = "backlight";
+
 pub struct Backlight {
     notifier: Inotify,
     max_brightness: PathBuf,
@@ -13,33 +15,27 @@ pub struct Backlight {
 }
 
 impl Backlight {
-    pub fn new() -> Self {
-        let notifier = Inotify::init(InitFlags::empty()).unwrap();
+    /// Fails for good without a backlight, the other modules keep running
+    pub fn new() -> Result<Self> {
+        let notifier = Inotify::init(InitFlags::empty())?;
         // assuming first entry in /sys/class/backlight/ is the display path,
         let Some(Ok(display_path)) = std::fs::read_dir("/sys/class/backlight/")
-            .unwrap_or_else(|error| {
-                eprintln!("Backlight not found: {error:?}");
-                exit(1);
-            })
+            .map_err(|e| crate::Fatal(format!("Backlight not found: {e}")))?
             .next()
         else {
-            eprintln!("No Backlight device found");
-            exit(1);
+            return Err(crate::Fatal(String::from("No Backlight device found")).into());
         };
         let max_brightness = display_path.path().join("max_brightness");
         let actual_brightness = display_path.path().join("actual_brightness");
         // listen only for brightness changes, ie if the file modified
         notifier
             .add_watch(&actual_brightness, AddWatchFlags::IN_MODIFY)
-            .unwrap_or_else(|error_nu| {
-                eprintln!("Failed to listen on {display_path:?}: {error_nu}");
-                exit(1);
-            });
-        Self {
+            .with_context(|| format!("Failed to listen on {:?}", display_path.path()))?;
+        Ok(Self {
             notifier,
             max_brightness,
             brightness: actual_brightness,
-        }
+        })
     }
     pub fn listen(&mut self) -> Result<()> {
         let max_bright: u64 = read_to_string(&self.max_brightness)?
@@ -52,16 +48,16 @@ impl Backlight {
         bright_fd.read_to_string(&mut bright_buf)?;
         let mut bright_val: u64 = bright_buf.trim().parse::<u64>()?;
         let mut bright_perc = ((bright_val as f64 / max_bright as f64) * 100.0) as u64;
-        crate::print(&Some(bright_perc));
+        crate::print(NAME, &Some(bright_perc));
         loop {
-            self.notifier.read_events()?.iter().for_each(|_event| {
+            for _event in self.notifier.read_events()? {
                 bright_buf.clear();
-                bright_fd.seek(SeekFrom::Start(0)).unwrap();
-                bright_fd.read_to_string(&mut bright_buf).unwrap();
-                bright_val = bright_buf.trim().parse::<u64>().unwrap();
+                bright_fd.seek(SeekFrom::Start(0))?;
+                bright_fd.read_to_string(&mut bright_buf)?;
+                bright_val = bright_buf.trim().parse::<u64>()?;
                 bright_perc = ((bright_val as f64 / max_bright as f64) * 100.0) as u64;
-                crate::print(&Some(bright_perc));
-            })
+                crate::print(NAME, &Some(bright_perc));
+            }
         }
     }
 }
@@ -0,0 +1,166 @@
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    io::{BufRead, BufReader},
+    process::{Command as Process, Stdio},
+    thread::sleep,
+    time::Duration,
+};
+
+/// `[[command]]` entry of the config
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandConfig {
+    /// module name, used for `pfui start command <name>` and as name of the output
+    pub name: String,
+    /// run with `sh -c`
+    pub exec: String,
+    /// run the command every `interval` seconds and use its whole output,
+    /// without it the command is expected to keep running and print a line for every update
+    pub interval: Option<u64>,
+    #[serde(default)]
+    pub format: Format,
+}
+
+/// How stdout of the command is turned into module data
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// parse it as json
+    #[default]
+    Json,
+    /// use it as string, a line of a streaming command or the whole output of one run every interval
+    #[serde(alias = "lines")]
+    Text,
+}
+
+impl Format {
+    fn parse(self, text: &str) -> Option<Value> {
+        match self {
+            Format::Json => serde_json::from_str(text)
+                .map_err(|e| warn!("Invalid json {text:?}: {e}"))
+                .ok(),
+            Format::Text => Some(Value::String(text.to_owned())),
+        }
+    }
+}
+
+/// Running command, killed when it's dropped so it doesn't outlive the module
+struct Child(std::process::Child);
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+pub struct Command {
+    config: CommandConfig,
+}
+
+impl Command {
+    /// Looks up the command module `name` in the config
+    pub fn new(name: &str) -> Result<Self> {
+        let config = crate::config::get()
+            .commands
+            .iter()
+            .find(|command| command.name == name)
+            .ok_or_else(|| anyhow!("No command module {name:?} in config"))?;
+        Ok(Self {
+            config: config.clone(),
+        })
+    }
+
+    fn process(&self) -> Process {
+        let mut process = Process::new("sh");
+        process.arg("-c").arg(&self.config.exec);
+        process
+    }
+
+    /// Runs the command every interval, a failing run sets the output to not ok
+    fn poll(&self, interval: Duration) -> Result<()> {
+        loop {
+            let output = self
+                .process()
+                .stderr(Stdio::inherit())
+                .output()
+                .with_context(|| format!("Failed to run {:?}", self.config.exec))?;
+            let data = if output.status.success() {
+                self.config
+                    .format
+                    .parse(String::from_utf8_lossy(&output.stdout).trim())
+            } else {
+                warn!("{:?} exited with {}", self.config.exec, output.status);
+                None
+            };
+            crate::print(&self.config.name, &data);
+            sleep(interval);
+        }
+    }
+
+    /// Prints an output for every line, the command is not supposed to exit
+    fn stream(&self) -> Result<()> {
+        let mut child = Child(
+            self.process()
+                .stdout(Stdio::piped())
+                .spawn()
+                .with_context(|| format!("Failed to run {:?}", self.config.exec))?,
+        );
+        let stdout = child.0.stdout.take().expect("stdout is piped");
+        for line in BufReader::new(stdout).lines() {
+            let line = line?;
+            crate::print(&self.config.name, &self.config.format.parse(line.trim()));
+        }
+        let status = child.0.wait()?;
+        Err(anyhow!("{:?} exited with {status}", self.config.exec))
+    }
+
+    pub fn start(&mut self) -> Result<()> {
+        match self.config.interval {
+            Some(secs) => self.poll(Duration::from_secs(secs)),
+            None => self.stream(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        assert_eq!(
+            Format::Json.parse(r#"{"a": 1}"#),
+            Some(serde_json::json!({"a": 1}))
+        );
+        assert_eq!(Format::Json.parse("not json"), None);
+        assert_eq!(Format::Text.parse("a\nb"), Some(Value::from("a\nb")));
+        let config: CommandConfig =
+            toml::from_str("name = \"wifi\"\nexec = \"true\"\nformat = \"lines\"").unwrap();
+        assert!(matches!(config.format, Format::Text));
+    }
+
+    #[test]
+    fn stream_kills_command_on_error() {
+        let pidfile = std::env::temp_dir().join(format!("pfui-command-{}", std::process::id()));
+        // invalid utf-8 fails reading the line, the command itself keeps running
+        let mut command = Command {
+            config: CommandConfig {
+                name: String::from("test-stream-kill"),
+                exec: format!(
+                    "echo $$ > {}; printf '\\377\\n'; exec sleep 30",
+                    pidfile.display()
+                ),
+                interval: None,
+                format: Format::Text,
+            },
+        };
+        assert!(command.start().is_err());
+        let pid = std::fs::read_to_string(&pidfile).unwrap();
+        let _ = std::fs::remove_file(&pidfile);
+        assert!(!std::path::Path::new("/proc").join(pid.trim()).exists());
+    }
+}
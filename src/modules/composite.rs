@@ -0,0 +1,207 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+use crate::{
+    hub::{self, Sink, SinkId},
+    Modules,
+};
+
+/// `[[composite]]` entry of the config
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CompositeConfig {
+    /// module name, used for `pfui start composite <name>` and as name of the output
+    pub name: String,
+    pub modules: Members,
+}
+
+/// Modules merged into a composite, written like for `pfui start`, e.g. "hyprland workspace"
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Members {
+    /// outputs are keyed by module name, e.g. `["mpd", "backlight"]`
+    Names(Vec<String>),
+    /// outputs are keyed by alias, e.g. `{ music = "mpd", brightness = "backlight" }`
+    Aliases(BTreeMap<String, String>),
+}
+
+/// Forwards outputs of the members to the composite
+struct Forward {
+    /// member module name to its keys in the composite output
+    keys: HashMap<String, Vec<String>>,
+    tx: Sender<(String, Value)>,
+}
+
+impl Sink for Forward {
+    fn publish(&mut self, module: &str, output: &Value) {
+        if let Some(keys) = self.keys.get(module) {
+            for key in keys {
+                let _ = self.tx.send((key.clone(), output.clone()));
+            }
+        }
+    }
+}
+
+/// Removes the forwarding sink when the composite stops, a restart adds a new one
+struct Registered(SinkId);
+
+impl Drop for Registered {
+    fn drop(&mut self) {
+        hub::remove_sink(self.0);
+    }
+}
+
+/// Outputs of the members by key, kept up to date by a sink in the hub until dropped
+struct Merged {
+    outputs: Map<String, Value>,
+    rx: Receiver<(String, Value)>,
+    _forward: Registered,
+}
+
+impl Merged {
+    /// `members` are pairs of key and module name. Members which already printed something are
+    /// picked up from the hub, in the same go as forwarding starts, so no output gets lost.
+    fn new(members: &[(String, String)]) -> Self {
+        let mut keys: HashMap<String, Vec<String>> = HashMap::new();
+        for (key, module) in members {
+            keys.entry(module.clone()).or_default().push(key.clone());
+        }
+        let mut outputs = Map::new();
+        let not_ok = serde_json::json!({"ok": 0, "data": null});
+        let (tx, rx) = channel();
+        let forward = Registered(hub::add_sink_with(|state| {
+            for (key, module) in members {
+                let output = state.get(module).cloned();
+                outputs.insert(key.clone(), output.unwrap_or_else(|| not_ok.clone()));
+            }
+            Forward { keys, tx }
+        }));
+        Self {
+            outputs,
+            rx,
+            _forward: forward,
+        }
+    }
+
+    /// Waits for the next output of a member, `None` if forwarding stopped
+    fn next(&mut self) -> Option<&Map<String, Value>> {
+        let (key, output) = self.rx.recv().ok()?;
+        self.outputs.insert(key, output);
+        Some(&self.outputs)
+    }
+}
+
+pub struct Composite {
+    config: CompositeConfig,
+}
+
+impl Composite {
+    /// Looks up the composite module `name` in the config
+    pub fn new(name: &str) -> Result<Self> {
+        let config = crate::config::get()
+            .composites
+            .iter()
+            .find(|composite| composite.name == name)
+            .ok_or_else(|| anyhow!("No composite module {name:?} in config"))?;
+        Ok(Self {
+            config: config.clone(),
+        })
+    }
+
+    /// Starts the members which are not running yet and prints the merged output whenever one
+    /// of them changes. Every member keeps its own `ok`, so the composite itself is always ok.
+    pub fn start(&mut self) -> Result<()> {
+        let members = match &self.config.modules {
+            Members::Names(specs) => specs
+                .iter()
+                .map(|spec| {
+                    Modules::from_spec(spec).map(|module| (module.name().to_owned(), module))
+                })
+                .collect::<Result<Vec<_>>>()?,
+            Members::Aliases(aliases) => aliases
+                .iter()
+                .map(|(alias, spec)| Modules::from_spec(spec).map(|module| (alias.clone(), module)))
+                .collect::<Result<Vec<_>>>()?,
+        };
+        let names: Vec<(String, String)> = members
+            .iter()
+            .map(|(key, module)| (key.clone(), module.name().to_owned()))
+            .collect();
+        let mut merged = Merged::new(&names);
+        for (_, module) in members {
+            std::thread::spawn(move || crate::run(&module));
+        }
+
+        crate::print(&self.config.name, &Some(&merged.outputs));
+        while let Some(outputs) = merged.next() {
+            crate::print(&self.config.name, &Some(outputs));
+        }
+        Err(anyhow!("Stopped receiving outputs of members"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn members(members: &[(&str, &str)]) -> Vec<(String, String)> {
+        members
+            .iter()
+            .map(|(key, module)| (key.to_string(), module.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn merges_members() {
+        hub::publish("composite-test-a", json!({"ok": 1, "data": 1}));
+        let mut merged = Merged::new(&members(&[
+            ("composite-test-a", "composite-test-a"),
+            ("alias", "composite-test-a"),
+            ("composite-test-b", "composite-test-b"),
+        ]));
+        // b hasn't printed yet
+        assert_eq!(
+            Value::Object(merged.outputs.clone()),
+            json!({
+                "composite-test-a": {"ok": 1, "data": 1},
+                "alias": {"ok": 1, "data": 1},
+                "composite-test-b": {"ok": 0, "data": null},
+            })
+        );
+        hub::publish("composite-test-b", json!({"ok": 1, "data": "b"}));
+        hub::publish("composite-test-a", json!({"ok": 0, "data": null}));
+        // a is under two keys
+        for _ in 0..3 {
+            merged.next().unwrap();
+        }
+        assert_eq!(
+            Value::Object(merged.outputs.clone()),
+            json!({
+                "composite-test-a": {"ok": 0, "data": null},
+                "alias": {"ok": 0, "data": null},
+                "composite-test-b": {"ok": 1, "data": "b"},
+            })
+        );
+        // other modules aren't forwarded
+        hub::publish("composite-test-c", json!({"ok": 1, "data": 3}));
+        assert!(merged.rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn stops_forwarding_when_dropped() {
+        let Merged { rx, _forward, .. } = Merged::new(&members(&[("d", "composite-test-d")]));
+        drop(_forward);
+        hub::publish("composite-test-d", json!({"ok": 1, "data": 4}));
+        // the sink and its sender are gone
+        assert_eq!(
+            rx.try_recv(),
+            Err(std::sync::mpsc::TryRecvError::Disconnected)
+        );
+    }
+}
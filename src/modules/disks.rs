@@ -1,5 +1,8 @@
+use anyhow::Context;
 use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
-use std::{ffi::OsStr, path::PathBuf, process::exit};
+use std::{ffi::OsStr, path::PathBuf};
+
+pub const NAME: &str = "disks";
 
 const MEDIA_DIR: &str = concat!("/run/media/", env!("USER"));
 
@@ -13,29 +16,24 @@ pub struct DiskMon {
 }
 
 impl DiskMon {
-    pub fn new() -> Self {
-        let notifier = Inotify::init(InitFlags::empty()).unwrap();
+    /// Fails while `MEDIA_DIR` doesn't exist, it's created with the first mount
+    pub fn new() -> anyhow::Result<Self> {
+        let notifier = Inotify::init(InitFlags::empty())?;
         let drive_disc = notifier
             .add_watch("/dev/", AddWatchFlags::IN_CREATE | AddWatchFlags::IN_DELETE)
-            .unwrap_or_else(|e| {
-                eprintln!("Failed to watch for devices {e:?}");
-                exit(1);
-            });
+            .context("Failed to watch for devices")?;
         let mount_disc = notifier
             .add_watch(
                 MEDIA_DIR,
                 AddWatchFlags::IN_CREATE | AddWatchFlags::IN_DELETE,
             )
-            .unwrap_or_else(|e| {
-                eprintln!("Failed to watch for devices {e:?}");
-                exit(1);
-            });
-        Self {
+            .with_context(|| format!("Failed to watch for mounts in {MEDIA_DIR}"))?;
+        Ok(Self {
             notifier,
             mount_disc,
             drive_disc,
             extern_drives: Vec::new(),
-        }
+        })
     }
     /// if a mount directory is created in /run/media/$USER/ means that drive is mounted, this function will map that mount point to that drive, lly for drive removal
     fn handle_mounts(&mut self, name: &OsStr, action: AddWatchFlags) {
@@ -134,9 +132,9 @@ impl DiskMon {
                         unreachable!();
                     }
                     if !self.extern_drives.is_empty() {
-                        crate::print(&Some(&self.extern_drives));
+                        crate::print(NAME, &Some(&self.extern_drives));
                     } else {
-                        crate::print::<()>(&None);
+                        crate::print::<()>(NAME, &None);
                     }
                     return;
                 }
@@ -15,6 +15,17 @@ pub enum HyprlandOpts {
     Keyboard,
 }
 
+impl HyprlandOpts {
+    /// name under which the output is published
+    pub fn name(&self) -> &'static str {
+        match self {
+            HyprlandOpts::Workspace => "hyprland-workspace",
+            HyprlandOpts::Window => "hyprland-window",
+            HyprlandOpts::Keyboard => "hyprland-keyboard",
+        }
+    }
+}
+
 #[derive(Serialize)]
 struct WorkspaceData {
     is_active: bool,
@@ -30,9 +41,10 @@ struct KeyboardLayout(hyprland::data::Keyboard);
 impl HyprlandListener {
     pub fn new(opts: &HyprlandOpts) -> Self {
         let mut listener = EventListener::new();
+        let name = opts.name();
         match opts {
             HyprlandOpts::Workspace => {
-                let print_workspace = || {
+                let print_workspace = move || {
                     if let Ok(wspaces) = Workspaces::get() {
                         let active_workspace = Workspace::get_active();
                         let mut wspaces: Vec<_> = wspaces
@@ -44,9 +56,9 @@ impl HyprlandListener {
                             })
                             .collect();
                         wspaces.sort_by(|s1, s2| s1.data.name.cmp(&s2.data.name));
-                        crate::print(&Some(wspaces));
+                        crate::print(name, &Some(wspaces));
                     } else {
-                        crate::print::<()>(&None);
+                        crate::print::<()>(name, &None);
                     }
                 };
                 // for initial;
@@ -76,11 +88,11 @@ impl HyprlandListener {
                 });
             }
             HyprlandOpts::Window => {
-                let print_window = || {
+                let print_window = move || {
                     if let Ok(Some(client)) = Client::get_active() {
-                        crate::print(&Some(client.class));
+                        crate::print(name, &Some(client.class));
                     } else {
-                        crate::print::<()>(&None);
+                        crate::print::<()>(name, &None);
                     }
                 };
                 listener.add_window_open_handler(move |win_event| {
@@ -101,16 +113,18 @@ impl HyprlandListener {
                 });
             }
             HyprlandOpts::Keyboard => {
-                let print_keyboard =
-                    || {
-                        if let Ok(devs) = Devices::get() {
-                            crate::print(&Some(devs.keyboards.get(0).expect(
+                let print_keyboard = move || {
+                    if let Ok(devs) = Devices::get() {
+                        crate::print(
+                            name,
+                            &Some(devs.keyboards.get(0).expect(
                                 "It is assumed that Atleast one keyboard should be present",
-                            )));
-                        } else {
-                            crate::print::<()>(&None);
-                        };
+                            )),
+                        );
+                    } else {
+                        crate::print::<()>(name, &None);
                     };
+                };
                 // while in the beginning prinnt the active keyboard
                 print_keyboard();
                 listener.add_keyboard_layout_change_handler(move |layout| {
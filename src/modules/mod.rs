@@ -1,6 +1,12 @@
 pub mod backlight;
+pub mod command;
+pub mod composite;
 pub mod disks;
 pub mod hyprland;
 pub mod mpd;
+#[cfg(feature = "mpris")]
+pub mod mpris;
+#[cfg(feature = "wasm")]
+pub mod plugin;
 pub mod pulseaudio;
 pub mod sway;
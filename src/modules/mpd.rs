@@ -1,18 +1,181 @@
 use anyhow::Result;
-use std::{thread::sleep, time::Duration};
+use clap::{Args, Subcommand, ValueEnum};
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, Condvar, Mutex},
+    thread::sleep,
+    time::{Duration, Instant},
+};
 
-use mpd::{idle::Subsystem, Client, Idle, Song as MpdSong, State as OldMpdState, Status};
-use serde::{Serialize, Serializer};
+use mpd::{
+    idle::Subsystem,
+    song::{Id, QueuePlace},
+    Client, Idle, Song as MpdSong, State as OldMpdState, Stats as MpdStats, Status,
+};
+use serde::{Deserialize, Serialize, Serializer};
 
-use crate::Module;
+use crate::{Fatal, Module};
 
-#[derive(Debug, Serialize)]
+mod conn;
+mod cover;
+mod lyrics;
+use conn::{Ack, Raw, Response, Stream, Target};
+use lyrics::Lrc;
+
+pub const NAME: &str = "mpd";
+
+/// Options of the mpd module, given as flags or in the `[mpd]` section of the config.
+/// Flags take precedence over the config, which takes precedence over `MPD_HOST`/`MPD_PORT`.
+#[derive(Debug, Clone, Default, Args, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Opts {
+    /// host name, socket path or `@` abstract socket, can be prefixed with `password@`.
+    /// Default is $MPD_HOST or localhost
+    #[arg(long)]
+    pub host: Option<String>,
+    /// default is $MPD_PORT or 6600
+    #[arg(long)]
+    pub port: Option<u16>,
+    #[arg(long)]
+    pub password: Option<String>,
+    /// partition to follow instead of the default one
+    #[arg(long)]
+    pub partition: Option<String>,
+    /// seconds between elapsed/progress updates while playing, e.g. 1.
+    /// By default they only change on player events
+    #[arg(long)]
+    pub tick: Option<f64>,
+    /// number of songs after the current one listed in `queue.upcoming`
+    #[arg(long)]
+    pub upcoming: Option<u32>,
+    /// mpd's music directory, to find cover images next to the songs.
+    /// Default is asking mpd, which only tells clients connected over a unix socket
+    #[arg(long)]
+    pub music_dir: Option<PathBuf>,
+    /// image used as `cover_path` when the song has no cover
+    #[arg(long)]
+    pub cover_fallback: Option<PathBuf>,
+    /// directory with `.lrc` files, named like the song in the music directory or `<artist> - <title>.lrc`.
+    /// Files next to the song are found without it
+    #[arg(long)]
+    pub lyrics_dir: Option<PathBuf>,
+    /// which tag `display_artist` prefers, default is artist
+    #[arg(long)]
+    pub display_artist: Option<DisplayArtist>,
+    /// put between several artists in `display_artist`, default is ", "
+    #[arg(long)]
+    pub artist_separator: Option<String>,
+    /// stickers of the current song shown in `song.stickers`, e.g. rating, can be given several times
+    #[arg(long = "sticker")]
+    pub stickers: Vec<String>,
+    /// add database statistics as `stats`
+    #[arg(long)]
+    pub with_stats: bool,
+    /// add `elapsed_str`, `duration_str`, `remaining_str` and `status_name` to `state`
+    #[arg(long)]
+    pub formatted: bool,
+    /// `state.status` is "playing", "paused" or "stopped" instead of 0, 1 or 2
+    #[arg(long)]
+    pub status_as_string: bool,
+}
+
+/// Opt-in changes to how `state` is written
+#[derive(Debug, Clone, Copy)]
+struct Format {
+    strings: bool,
+    status_as_string: bool,
+}
+
+/// Tag `song.display_artist` is made of, the other one is used if the song doesn't have it
+#[derive(Debug, Clone, Copy, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DisplayArtist {
+    #[default]
+    Artist,
+    AlbumArtist,
+}
+
+impl Opts {
+    /// Fills in what wasn't given on the command line from the config
+    fn with_config(&self) -> Self {
+        let config = &crate::config::get().mpd;
+        Self {
+            host: self.host.clone().or_else(|| config.host.clone()),
+            port: self.port.or(config.port),
+            password: self.password.clone().or_else(|| config.password.clone()),
+            partition: self.partition.clone().or_else(|| config.partition.clone()),
+            tick: self.tick.or(config.tick),
+            upcoming: self.upcoming.or(config.upcoming),
+            music_dir: self.music_dir.clone().or_else(|| config.music_dir.clone()),
+            cover_fallback: self
+                .cover_fallback
+                .clone()
+                .or_else(|| config.cover_fallback.clone()),
+            lyrics_dir: self
+                .lyrics_dir
+                .clone()
+                .or_else(|| config.lyrics_dir.clone()),
+            display_artist: self.display_artist.or(config.display_artist),
+            artist_separator: self
+                .artist_separator
+                .clone()
+                .or_else(|| config.artist_separator.clone()),
+            stickers: if self.stickers.is_empty() {
+                config.stickers.clone()
+            } else {
+                self.stickers.clone()
+            },
+            with_stats: self.with_stats || config.with_stats,
+            formatted: self.formatted || config.formatted,
+            status_as_string: self.status_as_string || config.status_as_string,
+        }
+    }
+
+    fn target(&self) -> Target {
+        Target::new(
+            self.host.as_deref(),
+            self.port,
+            self.password.as_deref(),
+            self.partition.as_deref(),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 struct Data {
     song: Song,
     state: State,
     options: Options,
+    queue: Queue,
+    /// audio outputs of the partition
+    outputs: Vec<Output>,
+    lyrics: Lyrics,
+    /// only with `--with-stats`
+    stats: Option<Stats>,
 }
-#[derive(Debug, Serialize)]
+/// Database statistics, times are in seconds
+#[derive(Debug, Clone, Serialize)]
+struct Stats {
+    artists: u32,
+    albums: u32,
+    songs: u32,
+    uptime: u64,
+    playtime: u64,
+    db_playtime: u64,
+    /// unix time of the last database update
+    db_update: u64,
+    /// is the database being updated right now?
+    updating: bool,
+}
+/// Lines of the synced lyrics at the elapsed time
+#[derive(Debug, Clone, Default, Serialize)]
+struct Lyrics {
+    current: Option<String>,
+    next: Option<String>,
+}
+#[derive(Debug, Clone, Serialize)]
 struct Song {
     file_path: Option<String>,
     title: Option<String>,
@@ -20,6 +183,18 @@ struct Song {
     artist: Option<String>,
     date: Option<String>,
     genre: Option<String>,
+    cover_path: Option<PathBuf>,
+    display_artist: Option<String>,
+    /// every tag of the song, tags the song has several times are arrays
+    tags: BTreeMap<String, Tag>,
+    /// the configured stickers the song has
+    stickers: BTreeMap<String, String>,
+}
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+enum Tag {
+    One(String),
+    Many(Vec<String>),
 }
 impl Song {
     fn empty() -> Self {
@@ -30,30 +205,200 @@ impl Song {
             artist: None,
             date: None,
             genre: None,
+            cover_path: None,
+            display_artist: None,
+            tags: BTreeMap::new(),
+            stickers: BTreeMap::new(),
         }
     }
+
+    /// Fills in the song from `currentsong` as mpd sent it, the mpd crate only keeps the last artist
+    fn set_tags(&mut self, response: &Response, display: DisplayArtist, separator: &str) {
+        self.file_path = response.get("file").map(str::to_owned);
+        for (key, value) in &response.pairs {
+            if NOT_TAGS.contains(&key.as_str()) {
+                continue;
+            }
+            match self.tags.get_mut(key) {
+                None => {
+                    self.tags.insert(key.clone(), Tag::One(value.clone()));
+                }
+                Some(Tag::One(first)) => {
+                    let first = std::mem::take(first);
+                    self.tags
+                        .insert(key.clone(), Tag::Many(vec![first, value.clone()]));
+                }
+                Some(Tag::Many(values)) => values.push(value.clone()),
+            }
+        }
+        let values = |tag: &str| match self.tags.get(tag) {
+            Some(Tag::One(value)) => vec![value.clone()],
+            Some(Tag::Many(values)) => values.clone(),
+            None => Vec::new(),
+        };
+        let (artists, album_artists) = (values("Artist"), values("AlbumArtist"));
+        let (preferred, fallback) = match display {
+            DisplayArtist::Artist => (&artists, &album_artists),
+            DisplayArtist::AlbumArtist => (&album_artists, &artists),
+        };
+        let display_artist = if preferred.is_empty() {
+            fallback
+        } else {
+            preferred
+        };
+        self.display_artist = (!display_artist.is_empty()).then(|| display_artist.join(separator));
+        let first = |tag| values(tag).into_iter().next();
+        self.title = first("Title");
+        self.album = first("Album");
+        self.date = first("Date");
+        self.genre = first("Genre");
+        self.artist = artists.into_iter().next();
+    }
 }
-#[derive(Debug, Serialize)]
+
+/// Keys of `currentsong` which aren't tags of the song
+const NOT_TAGS: [&str; 10] = [
+    "file",
+    "Last-Modified",
+    "Added",
+    "Format",
+    "Time",
+    "duration",
+    "Range",
+    "Pos",
+    "Id",
+    "Prio",
+];
+#[derive(Debug, Clone, Serialize)]
 struct State {
     elapsed: Option<u64>,
     duration: Option<u64>,
     progress: Option<i8>,
     status: Option<MpdState>,
+    #[serde(flatten)]
+    formatted: Option<Formatted>,
+    /// kbit/s
+    bitrate: Option<u32>,
+    audio: Option<Audio>,
+    /// id of the running database update
+    updating_db: Option<u32>,
+    error: Option<String>,
+}
+#[derive(Debug, Clone, Serialize)]
+struct Audio {
+    /// Hz
+    rate: u32,
+    /// 0 for floating point samples
+    bits: u8,
+    channels: u8,
 }
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 struct Options {
-    volume: i8,
+    /// None if mpd has no mixer
+    volume: Option<i8>,
     repeat: bool,
     random: bool,
+    single: bool,
+    consume: bool,
+    /// seconds
+    crossfade: Option<u64>,
+    /// dB, None if mpd doesn't report it
+    mixrampdb: Option<f32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Queue {
+    position: Option<u32>,
+    length: u32,
+    /// seconds, songs without a known duration (streams) don't count
+    duration: u64,
+    next: Option<QueueSong>,
+    upcoming: Vec<QueueSong>,
+}
+#[derive(Debug, Clone, Serialize)]
+struct QueueSong {
+    position: Option<u32>,
+    title: Option<String>,
+    artist: Option<String>,
+    duration: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Output {
+    id: u32,
+    name: String,
+    /// e.g. alsa, pipewire or httpd
+    plugin: Option<String>,
+    enabled: bool,
+}
+
+impl Output {
+    /// Parses the response to `outputs`, the mpd crate doesn't know the plugin
+    fn list(response: &Response) -> Vec<Self> {
+        let mut outputs: Vec<Self> = Vec::new();
+        for (key, value) in &response.pairs {
+            if key == "outputid" {
+                outputs.push(Output {
+                    id: value.parse().unwrap_or_default(),
+                    name: String::new(),
+                    plugin: None,
+                    enabled: false,
+                });
+                continue;
+            }
+            let Some(output) = outputs.last_mut() else {
+                continue;
+            };
+            match key.as_str() {
+                "outputname" => output.name = value.clone(),
+                "plugin" => output.plugin = Some(value.clone()),
+                "outputenabled" => output.enabled = value == "1",
+                _ => {}
+            }
+        }
+        outputs
+    }
 }
 
-#[derive(Debug)]
-struct MpdState(OldMpdState);
+/// Human readable versions of the fields of [`State`], only with `--formatted`
+#[derive(Debug, Clone, Serialize)]
+struct Formatted {
+    elapsed_str: Option<String>,
+    duration_str: Option<String>,
+    remaining_str: Option<String>,
+    status_name: Option<&'static str>,
+}
+
+/// `m:ss`, or `h:mm:ss` if `hours`
+fn format_time(seconds: u64, hours: bool) -> String {
+    let (h, m, s) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours {
+        format!("{h}:{m:02}:{s:02}")
+    } else {
+        format!("{}:{s:02}", seconds / 60)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MpdState(OldMpdState, bool);
+impl MpdState {
+    fn name(&self) -> &'static str {
+        match self.0 {
+            OldMpdState::Play => "playing",
+            OldMpdState::Pause => "paused",
+            OldMpdState::Stop => "stopped",
+        }
+    }
+}
 impl Serialize for MpdState {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
+        // with --status-as-string
+        if self.1 {
+            return serializer.serialize_str(self.name());
+        }
         if self.0 == OldMpdState::Play {
             return serializer.serialize_i8(0);
         } else if self.0 == OldMpdState::Pause {
@@ -65,46 +410,16 @@ impl Serialize for MpdState {
     }
 }
 
-impl From<&MpdSong> for Song {
-    fn from(value: &MpdSong) -> Self {
-        Song {
-            file_path: Some(value.file.clone()),
-            title: value.title.clone(),
-            album: value.tags.iter().find_map(|tag| {
-                if tag.0 == "Album" {
-                    Some(tag.1.clone())
-                } else {
-                    None
-                }
-            }),
-            artist: value.tags.iter().find_map(|tag| {
-                if tag.0 == "Artist" {
-                    Some(tag.1.clone())
-                } else {
-                    None
-                }
-            }),
-            date: value.tags.iter().find_map(|tag| {
-                if tag.0 == "Date" {
-                    Some(tag.1.clone())
-                } else {
-                    None
-                }
-            }),
-            genre: value.tags.iter().find_map(|tag| {
-                if tag.0 == "Genre" {
-                    Some(tag.1.clone())
-                } else {
-                    None
-                }
-            }),
-        }
+impl From<&Status> for State {
+    fn from(value: &Status) -> Self {
+        State::at(value, value.elapsed)
     }
 }
 
-impl From<&Status> for State {
-    fn from(value: &Status) -> Self {
-        let elapsed = value.elapsed.map(|elapsed| elapsed.as_secs());
+impl State {
+    /// State of `status` with a different elapsed time
+    fn at(value: &Status, elapsed: Option<Duration>) -> Self {
+        let elapsed = elapsed.map(|elapsed| elapsed.as_secs());
         let duration = value.duration.map(|duration| duration.as_secs());
         let progress = if let (Some(elapsed), Some(duration)) = (elapsed, duration) {
             if let (Ok(elapsed), Ok(duration)) = (i32::try_from(elapsed), i32::try_from(duration)) {
@@ -120,82 +435,667 @@ impl From<&Status> for State {
             elapsed,
             duration,
             progress,
-            status: Some(MpdState(value.state)),
+            status: Some(MpdState(value.state, false)),
+            formatted: None,
+            bitrate: value.bitrate,
+            audio: value.audio.map(|audio| Audio {
+                rate: audio.rate,
+                bits: audio.bits,
+                channels: audio.chans,
+            }),
+            updating_db: value.updating_db,
+            error: value.error.clone(),
         }
     }
 }
 
+impl State {
+    fn format(&mut self, format: Format) {
+        if let Some(status) = &mut self.status {
+            status.1 = format.status_as_string;
+        }
+        if !format.strings {
+            return;
+        }
+        // all in h:mm:ss if the song is that long, so the width doesn't change
+        let hours = self.duration.or(self.elapsed).unwrap_or(0) >= 3600;
+        self.formatted = Some(Formatted {
+            elapsed_str: self.elapsed.map(|elapsed| format_time(elapsed, hours)),
+            duration_str: self.duration.map(|duration| format_time(duration, hours)),
+            remaining_str: self
+                .duration
+                .zip(self.elapsed)
+                .map(|(duration, elapsed)| format_time(duration.saturating_sub(elapsed), hours)),
+            status_name: self.status.as_ref().map(MpdState::name),
+        });
+    }
+}
+
 impl From<&Status> for Options {
     fn from(value: &Status) -> Self {
         Options {
-            volume: value.volume,
+            // mpd reports -1 without a mixer
+            volume: (value.volume >= 0).then_some(value.volume),
             repeat: value.repeat,
             random: value.random,
+            single: value.single,
+            consume: value.consume,
+            crossfade: value.crossfade.map(|crossfade| crossfade.as_secs()),
+            // NaN if mpd didn't send it
+            mixrampdb: Some(value.mixrampdb).filter(|db| db.is_finite()),
         }
     }
 }
 
-impl
-    TryFrom<(
-        Result<std::option::Option<MpdSong>, mpd::error::Error>,
-        Result<Status, mpd::error::Error>,
-    )> for Data
-{
-    type Error = mpd::error::Error;
-    fn try_from(
-        value: (
-            Result<std::option::Option<MpdSong>, mpd::error::Error>,
-            Result<Status, mpd::error::Error>,
-        ),
-    ) -> Result<Self, Self::Error> {
-        let status = value.1?;
-        if let Ok(Some(current_song)) = value.0 {
-            Ok(Data {
-                song: Song::from(&current_song),
-                state: State::from(&status),
-                options: Options::from(&status),
-            })
-        } else {
-            Ok(Data {
-                song: Song::empty(),
-                state: State::from(&status),
-                options: Options::from(&status),
-            })
+impl Stats {
+    fn new(stats: &MpdStats, status: &Status) -> Self {
+        Stats {
+            artists: stats.artists,
+            albums: stats.albums,
+            songs: stats.songs,
+            uptime: stats.uptime.as_secs(),
+            playtime: stats.playtime.as_secs(),
+            db_playtime: stats.db_playtime.as_secs(),
+            db_update: stats.db_update.as_secs(),
+            updating: status.updating_db.is_some(),
         }
     }
 }
 
-fn get_info(conn: &mut Client) -> Option<Data> {
-    let current_song = conn.currentsong();
-    let status = conn.status();
-    Data::try_from((current_song, status)).ok()
+impl From<&Status> for Queue {
+    fn from(value: &Status) -> Self {
+        Queue {
+            position: value.song.map(|song| song.pos),
+            length: value.queue_len,
+            duration: 0,
+            next: None,
+            upcoming: Vec::new(),
+        }
+    }
 }
 
-pub struct Mpd {}
+impl From<&MpdSong> for QueueSong {
+    fn from(value: &MpdSong) -> Self {
+        QueueSong {
+            position: value.place.map(|place| place.pos),
+            title: value.title.clone(),
+            artist: value.artist.clone(),
+            duration: value.duration.map(|duration| duration.as_secs()),
+        }
+    }
+}
+
+impl Queue {
+    /// Fetches the next and upcoming songs, the total duration is only summed up again
+    /// when the queue changed since `last`
+    fn fetch(
+        &mut self,
+        conn: &mut Client<Stream>,
+        status: &Status,
+        upcoming: u32,
+        last: Option<&Last>,
+    ) -> mpd::error::Result<()> {
+        if let Some(next) = status.nextsong {
+            self.next = conn.songs(next.pos)?.first().map(QueueSong::from);
+        }
+        let start = status.song.map_or(0, |song| song.pos + 1);
+        if upcoming > 0 && start < status.queue_len {
+            let end = start.saturating_add(upcoming).min(status.queue_len);
+            self.upcoming = conn
+                .songs(start..end)?
+                .iter()
+                .map(QueueSong::from)
+                .collect();
+        }
+        self.duration = match last.filter(|last| last.status.queue_version == status.queue_version)
+        {
+            Some(last) => last.data.queue.duration,
+            None => conn
+                .queue()?
+                .iter()
+                .filter_map(|song| song.duration)
+                .sum::<Duration>()
+                .as_secs(),
+        };
+        Ok(())
+    }
+}
+
+impl From<&Status> for Data {
+    fn from(status: &Status) -> Self {
+        Data {
+            song: Song::empty(),
+            state: State::from(status),
+            options: Options::from(status),
+            queue: Queue::from(status),
+            outputs: Vec::new(),
+            lyrics: Lyrics::default(),
+            stats: None,
+        }
+    }
+}
+
+/// Parses the response to `status` like the mpd crate does, which skips `mixrampdb`,
+/// so the status is only fetched once
+fn parse_status(response: &Response) -> Result<Status> {
+    fn place(place: &mut Option<QueuePlace>) -> &mut QueuePlace {
+        place.get_or_insert(QueuePlace {
+            id: Id(0),
+            pos: 0,
+            prio: 0,
+        })
+    }
+    let seconds = |value: &str| {
+        value
+            .parse()
+            .ok()
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+    };
+    let mut status = Status {
+        mixrampdb: f32::NAN,
+        ..Status::default()
+    };
+    for (key, value) in &response.pairs {
+        match key.as_str() {
+            "volume" => status.volume = value.parse()?,
+            "repeat" => status.repeat = value == "1",
+            "random" => status.random = value == "1",
+            "single" => status.single = value == "1",
+            "consume" => status.consume = value == "1",
+            "playlist" => status.queue_version = value.parse()?,
+            "playlistlength" => status.queue_len = value.parse()?,
+            "state" => status.state = value.parse()?,
+            "songid" => place(&mut status.song).id = Id(value.parse()?),
+            "song" => place(&mut status.song).pos = value.parse()?,
+            "nextsongid" => place(&mut status.nextsong).id = Id(value.parse()?),
+            "nextsong" => place(&mut status.nextsong).pos = value.parse()?,
+            "elapsed" => status.elapsed = seconds(value),
+            "duration" => status.duration = seconds(value),
+            "bitrate" => status.bitrate = value.parse().ok(),
+            "xfade" => status.crossfade = value.parse().ok().map(Duration::from_secs),
+            "mixrampdb" => status.mixrampdb = value.parse().unwrap_or(f32::NAN),
+            // e.g. dsd formats aren't known to the mpd crate
+            "audio" => status.audio = value.parse().ok(),
+            "updating_db" => status.updating_db = value.parse().ok(),
+            "error" => status.error = Some(value.clone()),
+            _ => {}
+        }
+    }
+    Ok(status)
+}
+
+/// Last fetched data and the status it came from, elapsed is advanced from it while playing
+#[derive(Clone)]
+struct Last {
+    data: Data,
+    status: Status,
+    at: Instant,
+    lyrics: Option<Arc<Lrc>>,
+}
+
+impl Last {
+    /// Data at this moment and how long until the lyrics line changes.
+    /// The elapsed time is only advanced with `tick`, otherwise it's mpd's.
+    fn now(&self, tick: bool, format: Format) -> (Data, Option<Duration>) {
+        let elapsed = self.status.elapsed.map(|elapsed| {
+            if self.status.state != OldMpdState::Play {
+                return elapsed;
+            }
+            let elapsed = elapsed + self.at.elapsed();
+            self.status
+                .duration
+                .map_or(elapsed, |duration| elapsed.min(duration))
+        });
+        let mut data = self.data.clone();
+        if tick {
+            data.state = State::at(&self.status, elapsed);
+        }
+        data.state.format(format);
+        let mut until_next = None;
+        if let (Some(lyrics), Some(elapsed)) = (&self.lyrics, elapsed) {
+            let (current, next, next_start) = lyrics.at(elapsed);
+            data.lyrics = Lyrics {
+                current: current.map(str::to_owned),
+                next: next.map(str::to_owned),
+            };
+            until_next = next_start.map(|start| start.saturating_sub(elapsed));
+        }
+        (data, until_next)
+    }
+}
+
+/// State shared with the [`timer`] thread
+#[derive(Default)]
+struct Shared {
+    /// None while mpd can't be reached
+    last: Option<Last>,
+    /// set once the module is gone, so the thread ends
+    stopped: bool,
+}
+
+/// While playing, prints the elapsed time every `tick` and the lyrics whenever the line changes.
+/// Woken up whenever `last` changes.
+fn timer(shared: Arc<(Mutex<Shared>, Condvar)>, tick: Option<Duration>, format: Format) {
+    let (lock, changed) = &*shared;
+    let mut shared = lock.lock().unwrap();
+    while !shared.stopped {
+        let wait = match &shared.last {
+            Some(last) if last.status.state == OldMpdState::Play => {
+                let (data, until_next) = last.now(tick.is_some(), format);
+                crate::print(NAME, &Some(data));
+                match (tick, until_next) {
+                    (Some(tick), Some(until_next)) => Some(tick.min(until_next)),
+                    (tick, until_next) => tick.or(until_next),
+                }
+            }
+            _ => None,
+        };
+        shared = match wait {
+            Some(wait) => changed.wait_timeout(shared, wait).unwrap().0,
+            None => changed.wait(shared).unwrap(),
+        };
+    }
+}
+
+pub struct Mpd {
+    target: Target,
+    tick: Option<Duration>,
+    upcoming: u32,
+    music_dir: Option<PathBuf>,
+    cover_fallback: Option<PathBuf>,
+    lyrics_dir: Option<PathBuf>,
+    display_artist: DisplayArtist,
+    artist_separator: String,
+    stickers: Vec<String>,
+    with_stats: bool,
+    format: Format,
+    /// second connection for [`Raw`] commands, opened when needed
+    raw: RefCell<Option<Raw>>,
+    shared: Arc<(Mutex<Shared>, Condvar)>,
+}
+
+impl Mpd {
+    pub fn new(opts: &Opts) -> Self {
+        let opts = opts.with_config();
+        Self {
+            target: opts.target(),
+            tick: opts
+                .tick
+                .filter(|tick| *tick > 0.0)
+                .map(Duration::from_secs_f64),
+            upcoming: opts.upcoming.unwrap_or(0),
+            music_dir: opts.music_dir,
+            cover_fallback: opts.cover_fallback,
+            lyrics_dir: opts.lyrics_dir,
+            display_artist: opts.display_artist.unwrap_or_default(),
+            artist_separator: opts.artist_separator.unwrap_or_else(|| ", ".to_owned()),
+            stickers: opts.stickers,
+            with_stats: opts.with_stats,
+            format: Format {
+                strings: opts.formatted,
+                status_as_string: opts.status_as_string,
+            },
+            raw: RefCell::new(None),
+            shared: Arc::default(),
+        }
+    }
+
+    /// Runs `command` on the second connection, reconnecting once if mpd closed it in the meantime
+    fn raw(&self, command: &str, args: &[&str]) -> Result<Response> {
+        let mut raw = self.raw.borrow_mut();
+        if let Some(conn) = raw.as_mut() {
+            match conn.command(command, args) {
+                Err(e) if !e.is::<Ack>() => log::debug!("Reconnecting: {e:#}"),
+                result => return result,
+            }
+        }
+        raw.insert(self.target.raw()?).command(command, args)
+    }
+
+    fn get_info(&self, conn: &mut Client<Stream>, last: Option<&Last>) -> Option<Last> {
+        // fetched raw, the mpd crate drops tags and mixrampdb
+        let status = self
+            .raw("status", &[])
+            .and_then(|status| parse_status(&status));
+        let song = self.raw("currentsong", &[]);
+        let (status, song) = match (status, song) {
+            (Ok(status), Ok(song)) => (status, song),
+            (Err(e), _) | (_, Err(e)) => {
+                log::debug!("Failed to fetch the status: {e:#}");
+                return None;
+            }
+        };
+        let mut data = Data::from(&status);
+        if song.get("file").is_some() {
+            data.song
+                .set_tags(&song, self.display_artist, &self.artist_separator);
+        }
+        // the rest is left empty if it fails, the output is still useful without it
+        if let Err(e) = data.queue.fetch(conn, &status, self.upcoming, last) {
+            log::debug!("Failed to fetch the queue: {e}");
+        }
+        match self.raw("outputs", &[]) {
+            Ok(outputs) => data.outputs = Output::list(&outputs),
+            Err(e) => log::debug!("Failed to fetch the outputs: {e:#}"),
+        }
+        if let (false, Some(file)) = (self.stickers.is_empty(), &data.song.file_path) {
+            // mpd refuses when the song has no stickers
+            let mut stickers = conn.stickers_map("song", file).unwrap_or_default();
+            stickers.retain(|name, _| self.stickers.contains(name));
+            data.song.stickers = stickers.into_iter().collect();
+        }
+        if self.with_stats {
+            data.stats = conn
+                .stats()
+                .map(|stats| Stats::new(&stats, &status))
+                .map_err(|e| log::debug!("Failed to fetch the stats: {e}"))
+                .ok();
+        }
+        let mut lyrics = None;
+        match last.filter(|last| last.data.song.file_path == data.song.file_path) {
+            Some(last) => {
+                data.song.cover_path = last.data.song.cover_path.clone();
+                lyrics = last.lyrics.clone();
+            }
+            None => {
+                if let Some(file) = data.song.file_path.as_deref() {
+                    data.song.cover_path =
+                        cover::lookup(&self.target, self.music_dir.as_deref(), file);
+                    lyrics = Lrc::find(
+                        self.music_dir.as_deref(),
+                        self.lyrics_dir.as_deref(),
+                        file,
+                        data.song.artist.as_deref(),
+                        data.song.title.as_deref(),
+                    )
+                    .map(Arc::new);
+                }
+                if data.song.cover_path.is_none() {
+                    data.song.cover_path = self.cover_fallback.clone();
+                }
+            }
+        }
+        Some(Last {
+            data,
+            status,
+            at: Instant::now(),
+            lyrics,
+        })
+    }
+}
 
 impl Module for Mpd {
-    type Connection = Client;
+    type Connection = Client<Stream>;
     fn connect(&mut self, timeout: u64) -> Result<Self::Connection> {
-        let mut conn_ = Client::connect("127.0.0.1:6600");
-        while let Err(..) = conn_ {
-            conn_ = Client::connect("127.0.0.1:6600");
-            crate::print(&None::<Data>);
+        loop {
+            match self.target.connect() {
+                Ok(conn) => return Ok(conn),
+                Err(e) if e.is::<Fatal>() => return Err(e),
+                Err(e) => log::debug!("{e:#}"),
+            }
+            crate::print(NAME, &None::<Data>);
             sleep(Duration::new(timeout, 0));
         }
-        Ok(conn_?)
     }
     fn output(&self, conn: &mut Self::Connection) {
-        let info = get_info(conn);
-        crate::print(&info);
+        let (lock, changed) = &*self.shared;
+        // fetch without the lock, the timer keeps printing the previous state meanwhile
+        let previous = lock.lock().unwrap().last.clone();
+        let last = self.get_info(conn, previous.as_ref());
+        // hold the lock while printing, so the timer can't print an older state afterwards
+        let mut shared = lock.lock().unwrap();
+        shared.last = last;
+        let data = shared
+            .last
+            .as_ref()
+            .map(|last| last.now(self.tick.is_some(), self.format).0);
+        crate::print(NAME, &data);
+        changed.notify_all();
     }
     fn start(&mut self, timeout: u64) -> Result<()> {
         let mut conn = self.connect(timeout)?;
+        if self.music_dir.is_none() {
+            self.music_dir = conn.music_directory().ok().map(PathBuf::from);
+        }
+        let (shared, tick, format) = (self.shared.clone(), self.tick, self.format);
+        std::thread::spawn(move || timer(shared, tick, format));
         self.output(&mut conn);
+        let mut subsystems = vec![
+            Subsystem::Player,
+            Subsystem::Mixer,
+            Subsystem::Options,
+            Subsystem::Queue,
+            Subsystem::Output,
+            Subsystem::Sticker,
+            Subsystem::Update,
+        ];
+        if self.with_stats {
+            subsystems.push(Subsystem::Database);
+        }
         loop {
-            let guard = conn.idle(&[Subsystem::Player, Subsystem::Mixer, Subsystem::Options])?;
+            let guard = conn.idle(&subsystems)?;
             if guard.get().is_ok() {
                 self.output(&mut conn)
             }
         }
     }
 }
+
+impl Drop for Mpd {
+    fn drop(&mut self) {
+        let (lock, changed) = &*self.shared;
+        lock.lock().unwrap().stopped = true;
+        changed.notify_all();
+    }
+}
+
+/// Actions of `pfui ctl mpd`
+#[derive(Subcommand)]
+pub enum Action {
+    Play,
+    Pause,
+    #[command(about = "play if paused, pause if playing")]
+    Toggle,
+    Stop,
+    Next,
+    #[command(alias = "prev")]
+    Previous,
+    #[command(about = "rate the current song from 0 to 10 with a sticker")]
+    Rate {
+        /// 0 to 10, like most mpd clients
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=10))]
+        rating: u8,
+        /// name of the sticker
+        #[arg(long, default_value = "rating")]
+        sticker: String,
+    },
+}
+
+impl Action {
+    pub fn run(&self) -> Result<()> {
+        let mut conn = Opts::default().with_config().target().connect()?;
+        match self {
+            Action::Play => conn.play()?,
+            Action::Pause => conn.pause(true)?,
+            Action::Toggle => conn.toggle_pause()?,
+            Action::Stop => conn.stop()?,
+            Action::Next => conn.next()?,
+            Action::Previous => conn.prev()?,
+            Action::Rate { rating, sticker } => {
+                let song = conn
+                    .currentsong()?
+                    .ok_or_else(|| anyhow::anyhow!("No current song to rate"))?;
+                conn.set_sticker("song", &song.file, sticker, &rating.to_string())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use serde_json::json;
+
+    fn response(pairs: &[(&str, &str)]) -> Response {
+        Response {
+            pairs: pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            binary: None,
+        }
+    }
+
+    const SONG: [(&str, &str); 7] = [
+        ("file", "a/b.flac"),
+        ("Artist", "One"),
+        ("Artist", "Two"),
+        ("AlbumArtist", "Band"),
+        ("Title", "Song"),
+        ("duration", "1.5"),
+        ("Pos", "3"),
+    ];
+
+    #[test]
+    fn tags() {
+        let mut song = Song::empty();
+        song.set_tags(&response(&SONG), DisplayArtist::Artist, " & ");
+        assert_eq!(song.file_path.as_deref(), Some("a/b.flac"));
+        assert_eq!(song.title.as_deref(), Some("Song"));
+        assert_eq!(song.album, None);
+        assert_eq!(
+            serde_json::to_value(&song.tags).unwrap(),
+            json!({"AlbumArtist": "Band", "Artist": ["One", "Two"], "Title": "Song"})
+        );
+        assert_eq!(song.artist.as_deref(), Some("One"));
+        assert_eq!(song.display_artist.as_deref(), Some("One & Two"));
+    }
+
+    #[test]
+    fn display_artist() {
+        let display = |pairs: &[(&str, &str)], display| {
+            let mut song = Song::empty();
+            song.set_tags(&response(pairs), display, ", ");
+            song.display_artist
+        };
+        assert_eq!(
+            display(&SONG, DisplayArtist::AlbumArtist).as_deref(),
+            Some("Band")
+        );
+        // falls back to the other tag
+        let without_album_artist = &SONG[..3];
+        assert_eq!(
+            display(without_album_artist, DisplayArtist::AlbumArtist).as_deref(),
+            Some("One, Two")
+        );
+        let without_artist = [("AlbumArtist", "Band")];
+        assert_eq!(
+            display(&without_artist, DisplayArtist::Artist).as_deref(),
+            Some("Band")
+        );
+        assert_eq!(display(&[("Title", "Song")], DisplayArtist::Artist), None);
+    }
+
+    #[derive(Parser)]
+    struct Ctl {
+        #[command(subcommand)]
+        action: Action,
+    }
+
+    fn rate(args: &[&str]) -> Option<(u8, String)> {
+        let args = ["mpd", "rate"].iter().chain(args);
+        match Ctl::try_parse_from(args).ok()?.action {
+            Action::Rate { rating, sticker } => Some((rating, sticker)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn status() {
+        let status = parse_status(&response(&[
+            ("volume", "-1"),
+            ("repeat", "1"),
+            ("random", "0"),
+            ("playlist", "7"),
+            ("playlistlength", "12"),
+            ("mixrampdb", "-17.5"),
+            ("state", "play"),
+            ("song", "3"),
+            ("songid", "4"),
+            ("nextsong", "4"),
+            ("nextsongid", "5"),
+            ("elapsed", "61.250"),
+            ("duration", "200.000"),
+            ("audio", "dsd64:2"),
+        ]))
+        .unwrap();
+        assert_eq!(status.state, OldMpdState::Play);
+        assert_eq!((status.queue_version, status.queue_len), (7, 12));
+        assert_eq!(
+            status.song.map(|song| (song.pos, song.id)),
+            Some((3, Id(4)))
+        );
+        assert_eq!(
+            status.nextsong.map(|song| (song.pos, song.id)),
+            Some((4, Id(5)))
+        );
+        assert_eq!(status.elapsed, Some(Duration::from_millis(61250)));
+        // formats the mpd crate doesn't know are left out
+        assert_eq!(status.audio, None);
+        let data = Data::from(&status);
+        assert_eq!(data.options.volume, None);
+        assert!(data.options.repeat && !data.options.random);
+        assert_eq!(data.options.mixrampdb, Some(-17.5));
+        let stopped = parse_status(&response(&[("state", "stop")])).unwrap();
+        assert_eq!(Data::from(&stopped).options.mixrampdb, None);
+        assert!(parse_status(&response(&[("state", "dancing")])).is_err());
+    }
+
+    #[test]
+    fn outputs() {
+        let outputs = Output::list(&response(&[
+            ("outputid", "0"),
+            ("outputname", "Speakers"),
+            ("plugin", "pipewire"),
+            ("outputenabled", "1"),
+            ("attribute", "dop=0"),
+            ("outputid", "1"),
+            ("outputname", "Stream"),
+            ("outputenabled", "0"),
+        ]));
+        assert_eq!(
+            serde_json::to_value(outputs).unwrap(),
+            json!([
+                {"id": 0, "name": "Speakers", "plugin": "pipewire", "enabled": true},
+                {"id": 1, "name": "Stream", "plugin": null, "enabled": false},
+            ])
+        );
+        assert!(Output::list(&response(&[("outputname", "lost")])).is_empty());
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(format_time(0, false), "0:00");
+        assert_eq!(format_time(65, false), "1:05");
+        // minutes keep counting without hours
+        assert_eq!(format_time(3725, false), "62:05");
+        assert_eq!(format_time(65, true), "0:01:05");
+        assert_eq!(format_time(3725, true), "1:02:05");
+        assert_eq!(format_time(36000, true), "10:00:00");
+    }
+
+    #[test]
+    fn ratings() {
+        assert_eq!(rate(&["8"]), Some((8, String::from("rating"))));
+        assert_eq!(
+            rate(&["0", "--sticker", "stars"]),
+            Some((0, String::from("stars")))
+        );
+        assert_eq!(rate(&["10"]).map(|(rating, _)| rating), Some(10));
+        assert_eq!(rate(&["11"]), None);
+        assert_eq!(rate(&["-1"]), None);
+        assert_eq!(rate(&[]), None);
+    }
+}
@@ -0,0 +1,329 @@
+//! Connecting to mpd over tcp or a unix socket, configured like other mpd clients with
+//! `MPD_HOST`/`MPD_PORT` or with flags and the config.
+use anyhow::{anyhow, bail, Context, Result};
+use mpd::Client;
+use std::{
+    io::{BufRead, BufReader, Cursor, Read, Write},
+    net::TcpStream,
+    os::unix::net::UnixStream,
+    path::PathBuf,
+};
+
+use crate::Fatal;
+
+const DEFAULT_HOST: &str = "localhost";
+const DEFAULT_PORT: u16 = 6600;
+
+enum Socket {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+/// Connection to mpd, either over tcp or a unix socket
+pub struct Stream {
+    socket: Socket,
+    /// the banner is read while logging in, but the mpd crate wants to read it again
+    banner: Cursor<Vec<u8>>,
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.banner.position() < self.banner.get_ref().len() as u64 {
+            return self.banner.read(buf);
+        }
+        match &mut self.socket {
+            Socket::Tcp(stream) => stream.read(buf),
+            Socket::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match &mut self.socket {
+            Socket::Tcp(stream) => stream.write(buf),
+            Socket::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.socket {
+            Socket::Tcp(stream) => stream.flush(),
+            Socket::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Address {
+    Tcp(String, u16),
+    Unix(PathBuf),
+    /// linux abstract socket, written as `@name`
+    Abstract(String),
+}
+
+/// Resolved connection settings
+#[derive(Debug, PartialEq)]
+pub struct Target {
+    address: Address,
+    password: Option<String>,
+    partition: Option<String>,
+}
+
+impl Target {
+    /// `host` and `port` are taken from `MPD_HOST` and `MPD_PORT` if not given.
+    /// Like in other clients the host can be `password@host`, a socket path or `@abstract-socket`.
+    pub fn new(
+        host: Option<&str>,
+        port: Option<u16>,
+        password: Option<&str>,
+        partition: Option<&str>,
+    ) -> Self {
+        let env_host = std::env::var("MPD_HOST").ok();
+        let host = host.or(env_host.as_deref()).unwrap_or(DEFAULT_HOST);
+        // an abstract socket starts with @, so a password can't be empty
+        let (host_password, host) = match host.split_once('@') {
+            Some((password, host)) if !password.is_empty() => (Some(password), host),
+            _ => (None, host),
+        };
+        let port = port
+            .or_else(|| std::env::var("MPD_PORT").ok()?.parse().ok())
+            .unwrap_or(DEFAULT_PORT);
+        let address = if let Some(name) = host.strip_prefix('@') {
+            Address::Abstract(name.to_owned())
+        } else if let Some(path) = host.strip_prefix("~/") {
+            let home = std::env::var_os("HOME").unwrap_or_default();
+            Address::Unix(PathBuf::from(home).join(path))
+        } else if host.starts_with('/') {
+            Address::Unix(PathBuf::from(host))
+        } else {
+            Address::Tcp(host.to_owned(), port)
+        };
+        Self {
+            address,
+            password: password.or(host_password).map(str::to_owned),
+            partition: partition.map(str::to_owned),
+        }
+    }
+
+    fn stream(&self) -> std::io::Result<Stream> {
+        let socket = match &self.address {
+            Address::Tcp(host, port) => Socket::Tcp(TcpStream::connect((host.as_str(), *port))?),
+            Address::Unix(path) => Socket::Unix(UnixStream::connect(path)?),
+            Address::Abstract(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                Socket::Unix(UnixStream::connect_addr(&addr)?)
+            }
+        };
+        Ok(Stream {
+            socket,
+            banner: Cursor::default(),
+        })
+    }
+
+    /// Connects, logs in and switches to the partition. A wrong password is [`Fatal`],
+    /// retrying won't fix it.
+    pub fn connect(&self) -> Result<Client<Stream>> {
+        Ok(Client::new(self.raw()?.into_stream())?)
+    }
+
+    /// Like [`Target::connect`] without the mpd crate, see [`Raw`]
+    pub fn raw(&self) -> Result<Raw> {
+        let stream = self
+            .stream()
+            .with_context(|| format!("Failed to connect to mpd at {:?}", self.address))?;
+        let mut raw = Raw {
+            stream: BufReader::new(stream),
+            banner: String::new(),
+        };
+        raw.banner = raw.read_line()?;
+        if !raw.banner.starts_with("OK MPD ") {
+            bail!("Unexpected mpd banner: {}", raw.banner);
+        }
+        if let Some(password) = &self.password {
+            // mpd only refuses the password command if the password is wrong
+            raw.command("password", &[password])
+                .map_err(|e| match e.downcast::<Ack>() {
+                    Ok(Ack(e)) => Fatal(format!("mpd authentication failed: {e}")).into(),
+                    Err(e) => e,
+                })?;
+        }
+        if let Some(partition) = &self.partition {
+            raw.command("partition", &[partition])?;
+        }
+        Ok(raw)
+    }
+}
+
+/// Response to a command sent over [`Raw`]
+#[derive(Debug, Default)]
+pub struct Response {
+    pub pairs: Vec<(String, String)>,
+    /// binary chunk of e.g. `readpicture`
+    pub binary: Option<Vec<u8>>,
+}
+
+impl Response {
+    /// First value of `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find_map(|(k, v)| (k == key).then_some(v.as_str()))
+    }
+}
+
+/// mpd refused a command sent over [`Raw`], the connection can still be used
+#[derive(Debug)]
+pub struct Ack(pub String);
+
+impl std::fmt::Display for Ack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Ack {}
+
+/// Plain protocol connection, for the commands the mpd crate doesn't have or doesn't parse fully
+pub struct Raw {
+    stream: BufReader<Stream>,
+    banner: String,
+}
+
+impl Raw {
+    /// Hands the connection to the mpd crate
+    fn into_stream(self) -> Stream {
+        // mpd only sends something when asked, so nothing is left in the buffer
+        let mut stream = self.stream.into_inner();
+        stream.banner = Cursor::new(format!("{}\n", self.banner).into_bytes());
+        stream
+    }
+
+    /// Sends `command` with quoted `args` and reads the response, an `ACK` is an [`Ack`] error
+    pub fn command(&mut self, command: &str, args: &[&str]) -> Result<Response> {
+        let mut line = command.to_owned();
+        for arg in args {
+            line.push_str(" \"");
+            line.push_str(&arg.replace('\\', "\\\\").replace('"', "\\\""));
+            line.push('"');
+        }
+        line.push('\n');
+        self.stream.get_mut().write_all(line.as_bytes())?;
+        let mut response = Response::default();
+        loop {
+            let line = self.read_line()?;
+            if line == "OK" {
+                return Ok(response);
+            }
+            if let Some(error) = line.strip_prefix("ACK ") {
+                return Err(Ack(format!("mpd {command} failed: {error}")).into());
+            }
+            let (key, value) = line
+                .split_once(": ")
+                .ok_or_else(|| anyhow!("Unexpected response from mpd: {line}"))?;
+            if key == "binary" {
+                let mut binary = vec![0; value.parse()?];
+                self.stream.read_exact(&mut binary)?;
+                // the data is followed by a newline
+                self.read_line()?;
+                response.binary = Some(binary);
+            } else {
+                response.pairs.push((key.to_owned(), value.to_owned()));
+            }
+        }
+    }
+
+    fn read_line(&mut self) -> Result<String> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line)? == 0 {
+            bail!("mpd closed the connection");
+        }
+        line.truncate(line.trim_end_matches('\n').len());
+        Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(host: &str) -> Target {
+        Target::new(Some(host), Some(6600), None, None)
+    }
+
+    #[test]
+    fn hosts() {
+        assert_eq!(
+            target("localhost").address,
+            Address::Tcp(String::from("localhost"), 6600)
+        );
+        assert_eq!(
+            Target::new(Some("music"), Some(6601), None, Some("kitchen")),
+            Target {
+                address: Address::Tcp(String::from("music"), 6601),
+                password: None,
+                partition: Some(String::from("kitchen")),
+            }
+        );
+        assert_eq!(
+            target("/run/mpd/socket").address,
+            Address::Unix(PathBuf::from("/run/mpd/socket"))
+        );
+        let home = std::env::var_os("HOME").unwrap_or_default();
+        assert_eq!(
+            target("~/.mpd/socket").address,
+            Address::Unix(PathBuf::from(home).join(".mpd/socket"))
+        );
+    }
+
+    #[test]
+    fn passwords_and_abstract_sockets() {
+        let secret = target("secret@localhost");
+        assert_eq!(
+            secret.address,
+            Address::Tcp(String::from("localhost"), 6600)
+        );
+        assert_eq!(secret.password.as_deref(), Some("secret"));
+        let abstract_socket = target("@mpd");
+        assert_eq!(
+            abstract_socket.address,
+            Address::Abstract(String::from("mpd"))
+        );
+        assert_eq!(abstract_socket.password, None);
+        let both = target("secret@@mpd");
+        assert_eq!(both.address, Address::Abstract(String::from("mpd")));
+        assert_eq!(both.password.as_deref(), Some("secret"));
+        // a password given on its own wins over the one in the host
+        let explicit = Target::new(Some("secret@localhost"), None, Some("other"), None);
+        assert_eq!(explicit.password.as_deref(), Some("other"));
+    }
+
+    /// the only test using MPD_HOST and MPD_PORT, tests run in parallel
+    #[test]
+    fn environment() {
+        std::env::set_var("MPD_HOST", "envpass@envhost");
+        std::env::set_var("MPD_PORT", "6602");
+        let from_env = Target::new(None, None, None, None);
+        assert_eq!(
+            from_env.address,
+            Address::Tcp(String::from("envhost"), 6602)
+        );
+        assert_eq!(from_env.password.as_deref(), Some("envpass"));
+        // flags and the config come first
+        let given = Target::new(Some("flaghost"), Some(6603), None, None);
+        assert_eq!(given.address, Address::Tcp(String::from("flaghost"), 6603));
+        assert_eq!(given.password, None);
+        std::env::set_var("MPD_PORT", "not a port");
+        assert_eq!(
+            Target::new(None, None, None, None).address,
+            Address::Tcp(String::from("envhost"), DEFAULT_PORT)
+        );
+        std::env::remove_var("MPD_HOST");
+        std::env::remove_var("MPD_PORT");
+        assert_eq!(
+            Target::new(None, None, None, None).address,
+            Address::Tcp(String::from(DEFAULT_HOST), DEFAULT_PORT)
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//! Cover art of the current song, either an image next to the song in the music directory or the
+//! picture mpd finds for it, which is stored in `$XDG_CACHE_HOME/pfui/covers` named after its content.
+use anyhow::{anyhow, bail, Result};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use super::conn::{Raw, Target};
+
+/// file names (without extension) looked for next to the song, in order of preference
+const NAMES: [&str; 3] = ["cover", "folder", "front"];
+const EXTENSIONS: [&str; 3] = ["jpg", "jpeg", "png"];
+
+/// Directory pictures fetched from mpd are stored in
+fn cache_dir() -> Option<PathBuf> {
+    crate::config::xdg_dir("XDG_CACHE_HOME", ".cache").map(|dir| dir.join("covers"))
+}
+
+/// Looks for e.g. `cover.jpg` or `Folder.png` in the directory of `file` under `music_dir`
+fn find(music_dir: &Path, file: &str) -> Option<PathBuf> {
+    let dir = music_dir.join(file).parent()?.read_dir().ok()?;
+    dir.filter_map(|entry| {
+        let path = entry.ok()?.path();
+        let stem = path.file_stem()?.to_str()?.to_lowercase();
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        let rank = NAMES.iter().position(|name| *name == stem)?;
+        EXTENSIONS
+            .contains(&extension.as_str())
+            .then_some((rank, path))
+    })
+    .min()
+    .map(|(_, path)| path)
+}
+
+/// Reads the whole picture with `command`, `readpicture` or `albumart`, which send it in chunks
+fn fetch(raw: &mut Raw, command: &str, file: &str) -> Result<Option<Vec<u8>>> {
+    let mut data = Vec::new();
+    loop {
+        let response = raw.command(command, &[file, &data.len().to_string()])?;
+        let size = response.get("size").map(str::parse::<usize>).transpose()?;
+        // readpicture answers with nothing if the song has no picture
+        let (Some(size), Some(mut chunk)) = (size, response.binary) else {
+            return Ok(None);
+        };
+        if chunk.is_empty() && data.len() < size {
+            bail!(
+                "mpd {command} stopped sending at {} of {size} bytes",
+                data.len()
+            );
+        }
+        data.append(&mut chunk);
+        if data.len() >= size {
+            return Ok(Some(data));
+        }
+    }
+}
+
+fn extension(data: &[u8]) -> &'static str {
+    if data.starts_with(b"\x89PNG") {
+        "png"
+    } else if data.starts_with(&[0xff, 0xd8]) {
+        "jpg"
+    } else if data.starts_with(b"GIF8") {
+        "gif"
+    } else if data.get(8..12) == Some(b"WEBP") {
+        "webp"
+    } else {
+        "img"
+    }
+}
+
+/// File name of `data` in the cache, its SHA-256 so it stays the same across pfui versions
+fn file_name(data: &[u8]) -> String {
+    let mut name = Sha256::digest(data)
+        .iter()
+        .fold(String::new(), |mut name, byte| {
+            let _ = write!(name, "{byte:02x}");
+            name
+        });
+    name.push('.');
+    name.push_str(extension(data));
+    name
+}
+
+/// Stores `data` in the cache under its hash, unless it's already there
+fn store(data: &[u8]) -> Result<PathBuf> {
+    let dir = cache_dir().ok_or_else(|| anyhow!("Neither XDG_CACHE_HOME nor HOME are set"))?;
+    let path = dir.join(file_name(data));
+    if !path.exists() {
+        std::fs::create_dir_all(&dir)?;
+        // write somewhere else first, so nobody reads half a picture
+        let part = path.with_extension("part");
+        std::fs::write(&part, data)?;
+        std::fs::rename(&part, &path)?;
+    }
+    Ok(path)
+}
+
+/// Path to the cover of `file`: an image next to it, the picture embedded in it,
+/// or the cover mpd finds in its directory
+pub fn lookup(target: &Target, music_dir: Option<&Path>, file: &str) -> Option<PathBuf> {
+    if let Some(path) = music_dir.and_then(|dir| find(dir, file)) {
+        return Some(path);
+    }
+    let mut raw = target
+        .raw()
+        .map_err(|e| log::debug!("No cover for {file}: {e:#}"))
+        .ok()?;
+    for command in ["readpicture", "albumart"] {
+        match fetch(&mut raw, command, file) {
+            Ok(Some(data)) => {
+                return store(&data)
+                    .map_err(|e| log::warn!("Failed to store cover of {file}: {e:#}"))
+                    .ok()
+            }
+            Ok(None) => {}
+            Err(e) => log::debug!("{e:#}"),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_file_names() {
+        assert_eq!(
+            file_name(b"\x89PNG"),
+            "0f4636c78f65d3639ece5a064b5ae753e3408614a14fb18ab4d7540d2c248543.png"
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Synced lyrics from `.lrc` files, either next to the song or in a lyrics directory.
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Timestamped lines of an lrc file, sorted by time
+#[derive(Debug)]
+pub struct Lrc {
+    lines: Vec<(Duration, String)>,
+}
+
+/// Parses a `mm:ss.xx` timestamp
+fn parse_time(tag: &str) -> Option<Duration> {
+    let (minutes, seconds) = tag.split_once(':')?;
+    let minutes: u64 = minutes.parse().ok()?;
+    // some files use mm:ss:xx
+    let seconds: f64 = seconds.replacen(':', ".", 1).parse().ok()?;
+    Some(Duration::from_secs(minutes * 60) + Duration::try_from_secs_f64(seconds).ok()?)
+}
+
+impl Lrc {
+    pub fn parse(text: &str) -> Self {
+        let mut lines = Vec::new();
+        // milliseconds the lyrics are shown earlier
+        let mut offset = 0i64;
+        for line in text.lines() {
+            let mut rest = line.trim();
+            let mut times = Vec::new();
+            // a line can have several timestamps, e.g. for a repeated chorus
+            while let Some((tag, after)) =
+                rest.strip_prefix('[').and_then(|tag| tag.split_once(']'))
+            {
+                if let Some(time) = parse_time(tag) {
+                    times.push(time);
+                } else if let Some(value) = tag.strip_prefix("offset:") {
+                    offset = value.trim().parse().unwrap_or(0);
+                }
+                rest = after;
+            }
+            for time in times {
+                lines.push((time, rest.trim().to_owned()));
+            }
+        }
+        let shift = Duration::from_millis(offset.unsigned_abs());
+        for (time, _) in &mut lines {
+            *time = if offset > 0 {
+                time.saturating_sub(shift)
+            } else {
+                *time + shift
+            };
+        }
+        lines.sort_by_key(|(time, _)| *time);
+        Self { lines }
+    }
+
+    /// Looks for `<song>.lrc` next to the song, in `lyrics_dir` under the same path as in the music
+    /// directory, or `<artist> - <title>.lrc` in `lyrics_dir`
+    pub fn find(
+        music_dir: Option<&Path>,
+        lyrics_dir: Option<&Path>,
+        file: &str,
+        artist: Option<&str>,
+        title: Option<&str>,
+    ) -> Option<Self> {
+        let mut candidates: Vec<PathBuf> = Vec::new();
+        if let Some(dir) = music_dir {
+            candidates.push(dir.join(file).with_extension("lrc"));
+        }
+        if let Some(dir) = lyrics_dir {
+            candidates.push(dir.join(file).with_extension("lrc"));
+            if let (Some(artist), Some(title)) = (artist, title) {
+                candidates.push(dir.join(format!("{artist} - {title}.lrc")));
+            }
+        }
+        candidates
+            .iter()
+            .find_map(|path| std::fs::read_to_string(path).ok())
+            .map(|text| Self::parse(&text))
+    }
+
+    /// Current and next line at `elapsed`, and when the next line starts.
+    /// Empty lines, which are breaks between the lyrics, are `None`.
+    pub fn at(&self, elapsed: Duration) -> (Option<&str>, Option<&str>, Option<Duration>) {
+        let index = self.lines.partition_point(|(time, _)| *time <= elapsed);
+        fn text(line: Option<&(Duration, String)>) -> Option<&str> {
+            line.map(|(_, text)| text.as_str())
+                .filter(|text| !text.is_empty())
+        }
+        let next = self.lines.get(index);
+        (
+            text(index.checked_sub(1).and_then(|index| self.lines.get(index))),
+            text(next),
+            next.map(|(time, _)| *time),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    const SONG: &str = "[ti:Song]
+[ar:Artist]
+[00:01.00]first
+[00:03.50][00:10.00] chorus
+[00:05:00]
+no timestamp
+[00:07.25]last";
+
+    #[test]
+    fn parse() {
+        let lrc = Lrc::parse(SONG);
+        assert_eq!(
+            lrc.lines,
+            [
+                (secs(1.0), String::from("first")),
+                (secs(3.5), String::from("chorus")),
+                (secs(5.0), String::new()),
+                (secs(7.25), String::from("last")),
+                (secs(10.0), String::from("chorus")),
+            ]
+        );
+    }
+
+    #[test]
+    fn offsets() {
+        let times = |text: &str| -> Vec<Duration> {
+            Lrc::parse(text)
+                .lines
+                .into_iter()
+                .map(|(time, _)| time)
+                .collect()
+        };
+        // positive offsets show the lyrics earlier
+        assert_eq!(times("[offset:+500]\n[00:01.00]a"), [secs(0.5)]);
+        assert_eq!(times("[00:01.00]a\n[offset:-250]"), [secs(1.25)]);
+        assert_eq!(times("[offset:2000]\n[00:01.00]a"), [secs(0.0)]);
+        assert_eq!(times("[offset:soon]\n[00:01.00]a"), [secs(1.0)]);
+    }
+
+    #[test]
+    fn at() {
+        let lrc = Lrc::parse(SONG);
+        assert_eq!(lrc.at(secs(0.5)), (None, Some("first"), Some(secs(1.0))));
+        assert_eq!(
+            lrc.at(secs(1.0)),
+            (Some("first"), Some("chorus"), Some(secs(3.5)))
+        );
+        // the break after the first chorus
+        assert_eq!(lrc.at(secs(6.0)), (None, Some("last"), Some(secs(7.25))));
+        assert_eq!(
+            lrc.at(secs(8.0)),
+            (Some("last"), Some("chorus"), Some(secs(10.0)))
+        );
+        assert_eq!(lrc.at(secs(60.0)), (Some("chorus"), None, None));
+        assert_eq!(Lrc::parse("").at(secs(1.0)), (None, None, None));
+    }
+}
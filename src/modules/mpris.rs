@@ -0,0 +1,641 @@
+//! Media players on the session bus which implement [MPRIS](https://specifications.freedesktop.org/mpris-spec/latest/),
+//! like spotify, firefox or mpv. The output has the same shape as the mpd module's.
+use anyhow::{anyhow, Result};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::mpsc::{channel, Sender},
+    time::Instant,
+};
+use zbus::{
+    blocking::{fdo::DBusProxy, Connection, MessageIterator},
+    message::Type,
+    zvariant::{OwnedValue, Value},
+    MatchRule, Message,
+};
+
+pub const NAME: &str = "mpris";
+
+/// players own a name starting with this
+const PREFIX: &str = "org.mpris.MediaPlayer2.";
+const PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER: &str = "org.mpris.MediaPlayer2.Player";
+
+/// Options of the mpris module, given as flags or in the `[mpris]` section of the config
+#[derive(Debug, Clone, Default, Args, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Opts {
+    /// players preferred as active player, in order, e.g. `spotify`, matching the start of the
+    /// name after `org.mpris.MediaPlayer2.`. Can be given several times
+    #[arg(long)]
+    pub priority: Vec<String>,
+}
+
+impl Opts {
+    /// Takes the config's priority list if none was given on the command line
+    fn with_config(&self) -> Self {
+        let config = &crate::config::get().mpris;
+        Self {
+            priority: if self.priority.is_empty() {
+                config.priority.clone()
+            } else {
+                self.priority.clone()
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Data {
+    /// name of the active player, e.g. spotify or firefox.instance_1_42
+    player: String,
+    song: Song,
+    state: State,
+    options: Options,
+}
+#[derive(Debug, Serialize)]
+struct Song {
+    /// xesam:url
+    file_path: Option<String>,
+    title: Option<String>,
+    album: Option<String>,
+    artist: Option<String>,
+    date: Option<String>,
+    genre: Option<String>,
+    /// mpris:artUrl
+    art_url: Option<String>,
+    /// the art url as path, if it's a local file
+    cover_path: Option<PathBuf>,
+    display_artist: Option<String>,
+}
+#[derive(Debug, Serialize)]
+struct State {
+    elapsed: Option<u64>,
+    duration: Option<u64>,
+    progress: Option<i8>,
+    /// 0 = playing, 1 = paused, 2 = stopped, like mpd
+    status: Option<u8>,
+}
+#[derive(Debug, Serialize)]
+struct Options {
+    /// percent, can be more than 100, e.g. up to 130 with mpv
+    volume: Option<u16>,
+    repeat: bool,
+    random: bool,
+}
+
+fn string(value: &Value) -> Option<String> {
+    match value {
+        Value::Str(s) => Some(s.to_string()),
+        Value::ObjectPath(path) => Some(path.to_string()),
+        Value::Value(value) => string(value),
+        _ => None,
+    }
+}
+
+/// xesam:artist and others are lists, though some players send a single string
+fn strings(value: &Value) -> Vec<String> {
+    match value {
+        Value::Array(array) => array.iter().filter_map(string).collect(),
+        Value::Value(value) => strings(value),
+        value => string(value).into_iter().collect(),
+    }
+}
+
+/// mpris:length should be a signed integer, but not every player agrees
+fn int(value: &Value) -> Option<i64> {
+    match *value {
+        Value::I64(i) => Some(i),
+        Value::U64(u) => i64::try_from(u).ok(),
+        Value::I32(i) => Some(i.into()),
+        Value::U32(u) => Some(u.into()),
+        Value::F64(f) => Some(f as i64),
+        Value::Value(ref value) => int(value),
+        _ => None,
+    }
+}
+
+/// A player on the bus with the properties last sent by it
+#[derive(Debug, Default)]
+struct Player {
+    /// the bus name without [`PREFIX`]
+    name: String,
+    status: Option<String>,
+    metadata: HashMap<String, OwnedValue>,
+    loop_status: Option<String>,
+    shuffle: bool,
+    volume: Option<f64>,
+    /// when it last started or stopped playing
+    last_playing: Option<Instant>,
+}
+
+impl Player {
+    fn playing(&self) -> bool {
+        self.status.as_deref() == Some("Playing")
+    }
+
+    /// Applies changed properties of the player interface
+    fn update(&mut self, properties: HashMap<String, OwnedValue>) {
+        let was_playing = self.playing();
+        for (key, value) in properties {
+            match key.as_str() {
+                "PlaybackStatus" => self.status = string(&value),
+                "LoopStatus" => self.loop_status = string(&value),
+                "Shuffle" => self.shuffle = bool::try_from(&*value).unwrap_or_default(),
+                "Volume" => self.volume = f64::try_from(&*value).ok(),
+                "Metadata" => {
+                    self.metadata = value
+                        .try_clone()
+                        .ok()
+                        .and_then(|value| HashMap::try_from(value).ok())
+                        .unwrap_or_default()
+                }
+                _ => {}
+            }
+        }
+        if was_playing != self.playing() {
+            self.last_playing = Some(Instant::now());
+        }
+    }
+
+    fn meta(&self, key: &str) -> Vec<String> {
+        self.metadata
+            .get(key)
+            .map(|value| strings(value))
+            .unwrap_or_default()
+    }
+
+    fn data(&self, position: Option<i64>) -> Data {
+        let first = |key| self.meta(key).into_iter().next();
+        let artists = self.meta("xesam:artist");
+        let art_url = first("mpris:artUrl");
+        // lengths and positions are in microseconds
+        let duration = self
+            .metadata
+            .get("mpris:length")
+            .and_then(|length| int(length))
+            .and_then(|length| u64::try_from(length / 1_000_000).ok());
+        let elapsed = position.and_then(|position| u64::try_from(position / 1_000_000).ok());
+        let progress = match (elapsed, duration) {
+            (Some(elapsed), Some(duration)) if duration > 0 => {
+                i8::try_from((elapsed as f64 / duration as f64 * 100.0).round() as i64).ok()
+            }
+            _ => None,
+        };
+        Data {
+            player: self.name.clone(),
+            song: Song {
+                file_path: first("xesam:url"),
+                title: first("xesam:title"),
+                album: first("xesam:album"),
+                artist: artists.first().cloned(),
+                date: first("xesam:contentCreated"),
+                genre: first("xesam:genre"),
+                cover_path: art_url
+                    .as_deref()
+                    .and_then(|url| url.strip_prefix("file://"))
+                    .map(PathBuf::from),
+                art_url,
+                display_artist: (!artists.is_empty()).then(|| artists.join(", ")),
+            },
+            state: State {
+                elapsed,
+                duration,
+                progress,
+                status: match self.status.as_deref() {
+                    Some("Playing") => Some(0),
+                    Some("Paused") => Some(1),
+                    Some("Stopped") => Some(2),
+                    _ => None,
+                },
+            },
+            options: Options {
+                volume: self
+                    .volume
+                    .map(|volume| (volume.max(0.0) * 100.0).round() as u16),
+                repeat: self
+                    .loop_status
+                    .as_deref()
+                    .is_some_and(|status| status != "None"),
+                random: self.shuffle,
+            },
+        }
+    }
+}
+
+/// Forwards the messages matching `rule` to `tx`
+fn forward(
+    conn: &Connection,
+    rule: MatchRule<'static>,
+    tx: Sender<zbus::Result<Message>>,
+) -> Result<()> {
+    let messages = MessageIterator::for_match_rule(rule, conn, None)?;
+    std::thread::spawn(move || {
+        for message in messages {
+            if tx.send(message).is_err() {
+                return;
+            }
+        }
+    });
+    Ok(())
+}
+
+pub struct Mpris {
+    priority: Vec<String>,
+    /// by well known bus name
+    players: HashMap<String, Player>,
+    /// well known bus name of the unique names signals come from
+    owners: HashMap<String, String>,
+}
+
+impl Mpris {
+    pub fn new(opts: &Opts) -> Self {
+        Self {
+            priority: opts.with_config().priority,
+            players: HashMap::new(),
+            owners: HashMap::new(),
+        }
+    }
+
+    fn get(&self, conn: &Connection, bus_name: &str, property: &str) -> Result<OwnedValue> {
+        let reply = conn.call_method(
+            Some(bus_name),
+            PATH,
+            Some("org.freedesktop.DBus.Properties"),
+            "Get",
+            &(PLAYER, property),
+        )?;
+        Ok(reply.body().deserialize()?)
+    }
+
+    fn add(&mut self, conn: &Connection, bus_name: &str, owner: &str) -> Result<()> {
+        let reply = conn.call_method(
+            Some(bus_name),
+            PATH,
+            Some("org.freedesktop.DBus.Properties"),
+            "GetAll",
+            &PLAYER,
+        )?;
+        let mut player = Player {
+            name: bus_name.trim_start_matches(PREFIX).to_owned(),
+            ..Player::default()
+        };
+        player.update(reply.body().deserialize()?);
+        self.owners.insert(owner.to_owned(), bus_name.to_owned());
+        self.players.insert(bus_name.to_owned(), player);
+        Ok(())
+    }
+
+    fn remove(&mut self, bus_name: &str) {
+        self.players.remove(bus_name);
+        self.owners.retain(|_, name| name != bus_name);
+    }
+
+    /// A playing player before a paused one, then the one earliest in the priority list,
+    /// then the one which played most recently
+    fn active(&self) -> Option<(&String, &Player)> {
+        let rank = |player: &Player| {
+            self.priority
+                .iter()
+                .position(|name| player.name.starts_with(name.as_str()))
+                .map_or(0, |index| self.priority.len() - index)
+        };
+        self.players
+            .iter()
+            .max_by_key(|(_, player)| (player.playing(), rank(player), player.last_playing))
+    }
+
+    fn output(&self, conn: &Connection) {
+        let data = self.active().map(|(bus_name, player)| {
+            // the position isn't sent when it changes, players would have to send it constantly
+            let position = self
+                .get(conn, bus_name, "Position")
+                .ok()
+                .and_then(|position| int(&position));
+            player.data(position)
+        });
+        crate::print(NAME, &data);
+    }
+
+    fn handle(&mut self, conn: &Connection, message: &Message) -> Result<()> {
+        let header = message.header();
+        let member = header.member().map(|member| member.as_str());
+        let sender = header.sender().map(|sender| sender.to_string());
+        match member {
+            Some("NameOwnerChanged") => {
+                let (name, _old, new): (String, String, String) = message.body().deserialize()?;
+                if !name.starts_with(PREFIX) {
+                    return Ok(());
+                }
+                self.remove(&name);
+                if !new.is_empty() {
+                    self.add(conn, &name, &new)?;
+                }
+            }
+            Some("PropertiesChanged") => {
+                let (interface, changed, _invalidated): (
+                    String,
+                    HashMap<String, OwnedValue>,
+                    Vec<String>,
+                ) = message.body().deserialize()?;
+                let player = sender
+                    .and_then(|sender| self.owners.get(&sender))
+                    .and_then(|name| self.players.get_mut(name));
+                if let (PLAYER, Some(player)) = (interface.as_str(), player) {
+                    player.update(changed);
+                }
+            }
+            // Seeked only needs a new output, the position is read then
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn start(&mut self) -> Result<()> {
+        self.run(&Connection::session()?)
+    }
+
+    /// Follows the players on the bus of `conn`
+    fn run(&mut self, conn: &Connection) -> Result<()> {
+        let (tx, rx) = channel();
+        // subscribe before looking for players, so none is missed
+        forward(
+            conn,
+            MatchRule::builder()
+                .msg_type(Type::Signal)
+                .sender("org.freedesktop.DBus")?
+                .member("NameOwnerChanged")?
+                .arg0ns("org.mpris.MediaPlayer2")?
+                .build(),
+            tx.clone(),
+        )?;
+        forward(
+            conn,
+            MatchRule::builder()
+                .msg_type(Type::Signal)
+                .interface("org.freedesktop.DBus.Properties")?
+                .member("PropertiesChanged")?
+                .path(PATH)?
+                .build(),
+            tx.clone(),
+        )?;
+        forward(
+            conn,
+            MatchRule::builder()
+                .msg_type(Type::Signal)
+                .interface(PLAYER)?
+                .member("Seeked")?
+                .path(PATH)?
+                .build(),
+            tx,
+        )?;
+        let dbus = DBusProxy::new(conn)?;
+        for name in dbus.list_names()? {
+            if !name.starts_with(PREFIX) {
+                continue;
+            }
+            let owner = dbus.get_name_owner(name.as_ref())?;
+            if let Err(e) = self.add(conn, &name, &owner) {
+                log::warn!("Ignoring {name}: {e:#}");
+            }
+        }
+        self.output(conn);
+        for message in rx {
+            if let Err(e) = self.handle(conn, &message?) {
+                log::warn!("{e:#}");
+            }
+            self.output(conn);
+        }
+        Err(anyhow!("Lost the session bus"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use zbus::zvariant::{Array, ObjectPath};
+
+    fn owned<'a>(value: impl Into<Value<'a>>) -> OwnedValue {
+        value.into().try_into().unwrap()
+    }
+
+    #[test]
+    fn values() {
+        let path = ObjectPath::try_from("/org/mpd/track/1").unwrap();
+        assert_eq!(string(&Value::from("a")).as_deref(), Some("a"));
+        assert_eq!(
+            string(&Value::from(path)).as_deref(),
+            Some("/org/mpd/track/1")
+        );
+        assert_eq!(
+            string(&Value::Value(Box::new(Value::from("a")))).as_deref(),
+            Some("a")
+        );
+        assert_eq!(string(&Value::from(1u32)), None);
+        let list = Value::from(Array::from(vec!["a", "b"]));
+        assert_eq!(strings(&list), ["a", "b"]);
+        assert_eq!(strings(&Value::from("a")), ["a"]);
+        assert!(strings(&Value::from(true)).is_empty());
+        assert_eq!(int(&Value::from(-5i64)), Some(-5));
+        assert_eq!(int(&Value::from(5u64)), Some(5));
+        assert_eq!(int(&Value::from(u64::MAX)), None);
+        assert_eq!(int(&Value::from(5i32)), Some(5));
+        assert_eq!(int(&Value::from(5u32)), Some(5));
+        assert_eq!(int(&Value::from(5.9f64)), Some(5));
+        assert_eq!(int(&Value::Value(Box::new(Value::from(5i64)))), Some(5));
+        assert_eq!(int(&Value::from("5")), None);
+    }
+
+    #[test]
+    fn data() {
+        let metadata = HashMap::from([
+            ("xesam:title", Value::from("Song")),
+            ("xesam:artist", Value::from(Array::from(vec!["One", "Two"]))),
+            ("xesam:url", Value::from("file:///music/song.flac")),
+            ("mpris:artUrl", Value::from("file:///tmp/cover.png")),
+            ("mpris:length", Value::from(200_000_000i64)),
+        ]);
+        let mut player = Player {
+            name: String::from("mpv"),
+            ..Player::default()
+        };
+        player.update(HashMap::from([
+            (String::from("PlaybackStatus"), owned("Playing")),
+            (String::from("LoopStatus"), owned("Playlist")),
+            (String::from("Shuffle"), owned(true)),
+            (String::from("Volume"), owned(0.5)),
+            (String::from("Metadata"), owned(metadata)),
+        ]));
+        assert!(player.playing() && player.last_playing.is_some());
+        let data = serde_json::to_value(player.data(Some(50_000_000))).unwrap();
+        assert_eq!(
+            data,
+            serde_json::json!({
+                "player": "mpv",
+                "song": {
+                    "file_path": "file:///music/song.flac",
+                    "title": "Song",
+                    "album": null,
+                    "artist": "One",
+                    "date": null,
+                    "genre": null,
+                    "art_url": "file:///tmp/cover.png",
+                    "cover_path": "/tmp/cover.png",
+                    "display_artist": "One, Two"
+                },
+                "state": {"elapsed": 50, "duration": 200, "progress": 25, "status": 0},
+                "options": {"volume": 50, "repeat": true, "random": true}
+            })
+        );
+        let empty = Player::default().data(None);
+        assert_eq!(empty.state.status, None);
+        assert_eq!(empty.state.progress, None);
+        assert!(!empty.options.repeat);
+        let loud = Player {
+            volume: Some(1.3),
+            ..Player::default()
+        };
+        assert_eq!(loud.data(None).options.volume, Some(130));
+    }
+
+    #[test]
+    fn active_player() {
+        let mut mpris = Mpris::new(&Opts {
+            priority: vec![String::from("spotify"), String::from("mpv")],
+        });
+        let now = Instant::now();
+        let mut add = |name: &str, status: &str, last_playing: Option<Instant>| {
+            let player = Player {
+                name: name.to_owned(),
+                status: Some(status.to_owned()),
+                last_playing,
+                ..Player::default()
+            };
+            mpris.players.insert(format!("{PREFIX}{name}"), player);
+        };
+        add("firefox.instance_1", "Paused", Some(now));
+        add("vlc", "Paused", now.checked_sub(Duration::from_secs(5)));
+        let active = |mpris: &Mpris| mpris.active().map(|(_, player)| player.name.clone());
+        // the one which played most recently
+        assert_eq!(active(&mpris).as_deref(), Some("firefox.instance_1"));
+        // then priority
+        mpris.players.insert(
+            format!("{PREFIX}mpv"),
+            Player {
+                name: String::from("mpv"),
+                ..Player::default()
+            },
+        );
+        assert_eq!(active(&mpris).as_deref(), Some("mpv"));
+        // a playing player always wins
+        mpris
+            .players
+            .get_mut(&format!("{PREFIX}vlc"))
+            .unwrap()
+            .status = Some(String::from("Playing"));
+        assert_eq!(active(&mpris).as_deref(), Some("vlc"));
+        mpris.remove(&format!("{PREFIX}vlc"));
+        assert_eq!(active(&mpris).as_deref(), Some("mpv"));
+    }
+
+    /// A player like mpv on the bus
+    struct FakePlayer {
+        status: String,
+        title: String,
+    }
+
+    #[zbus::interface(name = "org.mpris.MediaPlayer2.Player")]
+    impl FakePlayer {
+        #[zbus(property)]
+        fn playback_status(&self) -> String {
+            self.status.clone()
+        }
+        #[zbus(property)]
+        fn metadata(&self) -> HashMap<String, OwnedValue> {
+            HashMap::from([(String::from("xesam:title"), owned(self.title.as_str()))])
+        }
+        #[zbus(property)]
+        fn volume(&self) -> f64 {
+            1.2
+        }
+        #[zbus(property)]
+        fn position(&self) -> i64 {
+            3_000_000
+        }
+    }
+
+    /// Outputs of the module
+    struct Outputs(std::sync::mpsc::Sender<serde_json::Value>);
+
+    impl crate::hub::Sink for Outputs {
+        fn publish(&mut self, module: &str, output: &serde_json::Value) {
+            if module == NAME {
+                let _ = self.0.send(output.clone());
+            }
+        }
+    }
+
+    #[test]
+    fn follows_players_on_private_bus() {
+        let Some((_bus, address)) = crate::testing::private_bus() else {
+            eprintln!("no dbus-daemon, skipping");
+            return;
+        };
+        let builder = || zbus::blocking::connection::Builder::address(address.as_str()).unwrap();
+        let (tx, rx) = channel();
+        let sink = crate::hub::add_sink(Outputs(tx));
+        let conn = builder().build().unwrap();
+        std::thread::spawn(move || Mpris::new(&Opts::default()).run(&conn));
+        // waits for an output with data matching `check`, `None` for `ok: 0`
+        let wait_for = |check: &dyn Fn(Option<&serde_json::Value>) -> bool| loop {
+            let output = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            if check(output.get("data").filter(|data| !data.is_null())) {
+                return;
+            }
+        };
+        // subscribed and no player yet
+        wait_for(&|data| data.is_none());
+
+        let player = builder()
+            .name("org.mpris.MediaPlayer2.test")
+            .unwrap()
+            .serve_at(
+                PATH,
+                FakePlayer {
+                    status: String::from("Playing"),
+                    title: String::from("Song"),
+                },
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+        // NameOwnerChanged
+        wait_for(&|data| {
+            data.is_some_and(|data| {
+                data["player"] == "test"
+                    && data["song"]["title"] == "Song"
+                    && data["state"]["status"] == 0
+                    && data["state"]["elapsed"] == 3
+                    && data["options"]["volume"] == 120
+            })
+        });
+
+        // PropertiesChanged
+        let iface = player
+            .object_server()
+            .interface::<_, FakePlayer>(PATH)
+            .unwrap();
+        iface.get_mut().status = String::from("Paused");
+        zbus::block_on(iface.get().playback_status_changed(iface.signal_context())).unwrap();
+        wait_for(&|data| data.is_some_and(|data| data["state"]["status"] == 1));
+
+        // the name is gone with the player
+        drop(iface);
+        drop(player);
+        wait_for(&|data| data.is_none());
+        crate::hub::remove_sink(sink);
+    }
+}
@@ -0,0 +1,381 @@
+//! Host for third-party modules compiled to WebAssembly, see `docs/plugins.md` for the interface.
+//! Plugins can't access anything on their own, they declare files, sockets and a timer to watch
+//! and pfui sends them what happens there, as far as the user allowed it in the config.
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info, warn};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+    time::{Duration, Instant},
+};
+use wasmi::{Caller, Config, Engine, Extern, Linker, Memory, Module, Store, TypedFunc};
+
+/// fuel given to every call into the plugin, so a plugin stuck in a loop can't block pfui
+const FUEL: u64 = 100_000_000;
+
+/// What the plugin wants to watch, returned by `pfui_init`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Manifest {
+    /// files whose contents are sent on start and whenever they are modified
+    files: Vec<PathBuf>,
+    /// unix sockets to connect to, every line read from them is sent
+    sockets: Vec<PathBuf>,
+    /// milliseconds between timer events
+    interval: Option<u64>,
+}
+
+/// What the user allows the plugin to watch, `files` and `sockets` of `[plugins.<name>]`
+#[derive(Debug, Default, Deserialize)]
+struct Allowed {
+    #[serde(default)]
+    files: Vec<PathBuf>,
+    #[serde(default)]
+    sockets: Vec<PathBuf>,
+}
+
+impl Allowed {
+    /// Refuses a manifest asking for anything the user didn't allow, that's not going to change
+    /// by restarting the plugin
+    fn check(&self, name: &str, manifest: &Manifest) -> Result<()> {
+        let refused = |kind: &str, path: &Path| {
+            crate::Fatal(format!(
+                "{name} wants to watch {path:?}, which isn't in {kind} of [plugins.{name}]"
+            ))
+        };
+        if let Some(path) = manifest
+            .files
+            .iter()
+            .find(|path| !self.files.contains(path))
+        {
+            return Err(refused("files", path).into());
+        }
+        if let Some(path) = manifest
+            .sockets
+            .iter()
+            .find(|path| !self.sockets.contains(path))
+        {
+            return Err(refused("sockets", path).into());
+        }
+        Ok(())
+    }
+}
+
+/// `len` bytes at `ptr` of the plugin's memory, `None` if they're not all inside it
+fn slice(data: &[u8], ptr: u32, len: u32) -> Option<&[u8]> {
+    let start = usize::try_from(ptr).ok()?;
+    let end = start.checked_add(usize::try_from(len).ok()?)?;
+    data.get(start..end)
+}
+
+/// Events sent to `pfui_event`, serialized as `{"kind": "<kind>", ...}`
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum Event {
+    /// sent once after `pfui_init`
+    Start,
+    File {
+        path: PathBuf,
+        contents: String,
+    },
+    Socket {
+        path: PathBuf,
+        line: String,
+    },
+    Timer,
+}
+
+/// Directory plugins are loaded from, `$XDG_DATA_HOME/pfui/plugins`
+pub fn dir() -> Option<PathBuf> {
+    crate::config::xdg_dir("XDG_DATA_HOME", ".local/share").map(|dir| dir.join("plugins"))
+}
+
+/// An instantiated plugin
+struct Instance {
+    store: Store<String>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    init: TypedFunc<(i32, i32), i64>,
+    event: TypedFunc<(i32, i32), i64>,
+}
+
+impl Instance {
+    fn load(name: &str, path: &Path) -> Result<Self> {
+        let wasm = std::fs::read(path).with_context(|| format!("Failed to read {path:?}"))?;
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &wasm)?;
+        // the store data is the plugin name, for logging
+        let mut store = Store::new(&engine, name.to_owned());
+        let mut linker = Linker::new(&engine);
+        // the only thing plugins can import
+        linker.func_wrap(
+            "pfui",
+            "log",
+            |caller: Caller<'_, String>, ptr: i32, len: i32| {
+                let Some(Extern::Memory(memory)) = caller.get_export("memory") else {
+                    return;
+                };
+                let (Ok(ptr), Ok(len)) = (u32::try_from(ptr), u32::try_from(len)) else {
+                    return;
+                };
+                if let Some(message) = slice(memory.data(&caller), ptr, len) {
+                    info!("{}: {}", caller.data(), String::from_utf8_lossy(message));
+                }
+            },
+        )?;
+        let instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| anyhow!("Plugin doesn't export its memory"))?;
+        Ok(Self {
+            alloc: instance.get_typed_func(&store, "pfui_alloc")?,
+            init: instance.get_typed_func(&store, "pfui_init")?,
+            event: instance.get_typed_func(&store, "pfui_event")?,
+            memory,
+            store,
+        })
+    }
+
+    /// Passes `input` to `func` and returns what it returned, `None` if it returned nothing
+    fn call(&mut self, func: TypedFunc<(i32, i32), i64>, input: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.store.set_fuel(FUEL)?;
+        let len = i32::try_from(input.len())?;
+        let ptr = self.alloc.call(&mut self.store, len)?;
+        self.memory.write(&mut self.store, ptr as usize, input)?;
+        // pointer in the upper, length in the lower 32 bits
+        let packed = func.call(&mut self.store, (ptr, len))? as u64;
+        let (ptr, len) = ((packed >> 32) as u32, packed as u32);
+        if len == 0 {
+            return Ok(None);
+        }
+        let output = slice(self.memory.data(&self.store), ptr, len)
+            .ok_or_else(|| anyhow!("Plugin returned {len} bytes at {ptr}, outside its memory"))?;
+        Ok(Some(output.to_vec()))
+    }
+}
+
+/// Sends the contents of `files` on start and whenever one is modified
+fn watch_files(files: Vec<PathBuf>, tx: Sender<Result<Event>>) -> Result<()> {
+    let notifier = Inotify::init(InitFlags::empty())?;
+    let mut watches = HashMap::new();
+    for path in files {
+        let wd = notifier
+            .add_watch(
+                &path,
+                AddWatchFlags::IN_MODIFY | AddWatchFlags::IN_CLOSE_WRITE,
+            )
+            .with_context(|| format!("Failed to watch {path:?}"))?;
+        watches.insert(wd, path);
+    }
+    let read = |path: &PathBuf| {
+        std::fs::read_to_string(path)
+            .map(|contents| Event::File {
+                path: path.clone(),
+                contents,
+            })
+            .with_context(|| format!("Failed to read {path:?}"))
+    };
+    for path in watches.values() {
+        let _ = tx.send(read(path));
+    }
+    std::thread::spawn(move || loop {
+        let events = match notifier.read_events() {
+            Ok(events) => events,
+            Err(e) => {
+                let _ = tx.send(Err(e.into()));
+                return;
+            }
+        };
+        for event in events {
+            if let Some(path) = watches.get(&event.wd) {
+                if tx.send(read(path)).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Sends every line read from the socket, the socket closing is an error
+fn watch_socket(path: PathBuf, tx: Sender<Result<Event>>) -> Result<()> {
+    let stream =
+        UnixStream::connect(&path).with_context(|| format!("Failed to connect to {path:?}"))?;
+    std::thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            let event = line.map_err(anyhow::Error::from).map(|line| Event::Socket {
+                path: path.clone(),
+                line,
+            });
+            if tx.send(event).is_err() {
+                return;
+            }
+        }
+        let _ = tx.send(Err(anyhow!("{path:?} closed")));
+    });
+    Ok(())
+}
+
+/// Waits for the next event, a timer event is due at `next` and then every `interval`,
+/// however many other events arrive
+fn next_event(
+    rx: &Receiver<Result<Event>>,
+    interval: Option<Duration>,
+    next: &mut Instant,
+) -> Option<Result<Event>> {
+    let Some(interval) = interval else {
+        return rx.recv().ok();
+    };
+    let timeout = next.saturating_duration_since(Instant::now());
+    match rx.recv_timeout(timeout) {
+        Ok(event) => return Some(event),
+        Err(RecvTimeoutError::Timeout) => {}
+        Err(RecvTimeoutError::Disconnected) => std::thread::sleep(timeout),
+    }
+    // a plugin which took longer than the interval doesn't get the missed timers all at once
+    *next = (*next + interval).max(Instant::now());
+    Some(Ok(Event::Timer))
+}
+
+pub struct Plugin {
+    name: String,
+}
+
+impl Plugin {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+        }
+    }
+
+    /// Sends the event to the plugin and prints what it returned
+    fn handle(&self, instance: &mut Instance, event: &Event) -> Result<()> {
+        debug!("{}: {event:?}", self.name);
+        let Some(output) = instance.call(instance.event, &serde_json::to_vec(event)?)? else {
+            return Ok(());
+        };
+        let data: Value = serde_json::from_slice(&output)?;
+        crate::print(&self.name, &(!data.is_null()).then_some(data));
+        Ok(())
+    }
+
+    pub fn start(&mut self) -> Result<()> {
+        let path = dir()
+            .ok_or_else(|| anyhow!("Neither XDG_DATA_HOME nor HOME are set"))?
+            .join(format!("{}.wasm", self.name));
+        let mut instance = Instance::load(&self.name, &path)?;
+        // plugin specific settings from `[plugins.<name>]`
+        let settings = crate::config::get().plugins.get(&self.name);
+        let allowed = settings
+            .map(|settings| Allowed::deserialize(settings.clone()))
+            .transpose()
+            .with_context(|| format!("Invalid files or sockets in [plugins.{}]", self.name))?
+            .unwrap_or_default();
+        let config = settings
+            .map(serde_json::to_vec)
+            .transpose()?
+            .unwrap_or_else(|| b"{}".to_vec());
+        let manifest: Manifest = match instance.call(instance.init, &config)? {
+            Some(manifest) => serde_json::from_slice(&manifest)?,
+            None => Manifest::default(),
+        };
+        debug!("{}: {manifest:?}", self.name);
+        allowed.check(&self.name, &manifest)?;
+
+        let (tx, rx) = channel();
+        watch_files(manifest.files, tx.clone())?;
+        for socket in manifest.sockets {
+            watch_socket(socket, tx.clone())?;
+        }
+        drop(tx);
+        self.handle(&mut instance, &Event::Start)?;
+        let interval = manifest.interval.map(Duration::from_millis);
+        let mut next = Instant::now() + interval.unwrap_or_default();
+        loop {
+            let Some(event) = next_event(&rx, interval, &mut next) else {
+                // nothing to watch, the plugin is done after start
+                warn!("{} doesn't watch anything", self.name);
+                return Ok(());
+            };
+            self.handle(&mut instance, &event?)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_bounds() {
+        let data = [1, 2, 3, 4];
+        assert_eq!(slice(&data, 1, 2), Some(&data[1..3]));
+        assert_eq!(slice(&data, 0, 4), Some(&data[..]));
+        assert_eq!(slice(&data, 3, 2), None);
+        assert_eq!(slice(&data, 5, 0), None);
+        assert_eq!(slice(&data, u32::MAX, u32::MAX), None);
+    }
+
+    #[test]
+    fn only_allowed_paths() {
+        let allowed: Allowed = toml::from_str(
+            r#"
+            files = ["/sys/class/power_supply/BAT0/capacity"]
+            warn_below = 15
+            "#,
+        )
+        .unwrap();
+        let manifest = Manifest {
+            files: vec![PathBuf::from("/sys/class/power_supply/BAT0/capacity")],
+            ..Manifest::default()
+        };
+        assert!(allowed.check("battery", &manifest).is_ok());
+        let manifest = Manifest {
+            files: vec![PathBuf::from("/home/user/.ssh/id_ed25519")],
+            ..Manifest::default()
+        };
+        let e = allowed.check("battery", &manifest).unwrap_err();
+        assert!(e.is::<crate::Fatal>());
+        let manifest = Manifest {
+            sockets: vec![PathBuf::from("/run/user/1000/bus")],
+            ..Manifest::default()
+        };
+        assert!(allowed.check("battery", &manifest).is_err());
+    }
+
+    #[test]
+    fn timer_keeps_its_interval() {
+        let (tx, rx) = channel();
+        // a busy socket
+        std::thread::spawn(move || {
+            for _ in 0..40 {
+                let event = Event::Socket {
+                    path: PathBuf::from("/socket"),
+                    line: String::new(),
+                };
+                if tx.send(Ok(event)).is_err() {
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        });
+        let interval = Some(Duration::from_millis(50));
+        let started = Instant::now();
+        let mut next = started + Duration::from_millis(50);
+        let mut timers = 0;
+        while started.elapsed() < Duration::from_millis(190) {
+            if let Some(Ok(Event::Timer)) = next_event(&rx, interval, &mut next) {
+                timers += 1;
+            }
+        }
+        assert!(timers >= 3, "only {timers} timer events");
+    }
+}
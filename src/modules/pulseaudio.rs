@@ -1,6 +1,9 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
+use clap::{Args, Subcommand};
 use std::{
+    cell::Cell,
     collections::HashSet,
+    rc::Rc,
     sync::{Arc, Mutex},
     thread::sleep,
     time::Duration,
@@ -8,27 +11,123 @@ use std::{
 
 use pulse::{
     callbacks::ListResult,
+    channelmap::{Map, Position},
     context::{
-        introspect::{SinkInfo, SourceInfo},
+        introspect::{
+            CardInfo, CardProfileInfo, SinkInfo, SinkInputInfo, SinkPortInfo, SourceInfo,
+            SourceOutputInfo, SourcePortInfo,
+        },
+        subscribe::{Facility, InterestMaskSet, Operation},
         Context,
     },
+    def::{SinkFlagSet, SourceFlagSet},
     mainloop::standard::{IterateResult, Mainloop},
+    proplist::{properties, Proplist},
+    volume::{ChannelVolumes, Volume, VolumeDB},
 };
 use serde::Serialize;
 
 use crate::Module;
 
+mod meter;
+#[cfg(feature = "pipewire")]
+mod pipewire;
+mod privacy;
+
+pub const NAME: &str = "pulseaudio";
+
+#[derive(Debug, Default, Args)]
+pub struct Opts {
+    #[command(subcommand)]
+    mode: Option<Mode>,
+    /// read the sinks, sources and streams from PipeWire directly, without pipewire-pulse
+    #[cfg(feature = "pipewire")]
+    #[arg(long)]
+    pipewire: bool,
+}
+
+/// What to print instead of the sinks, sources and streams
+#[derive(Debug, Clone, Subcommand)]
+enum Mode {
+    /// applications using the microphone or camera
+    Privacy,
+    /// peak level of the default sink's monitor, for a VU meter
+    Meter {
+        /// record this source instead, e.g. a microphone
+        #[arg(long)]
+        source: Option<String>,
+        /// peaks printed per second, from 1 to 1000
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..=1000))]
+        frequency: u32,
+    },
+}
+
+impl Opts {
+    /// name under which the output is published
+    pub fn name(&self) -> &'static str {
+        match self.mode {
+            None => NAME,
+            Some(Mode::Privacy) => privacy::NAME,
+            Some(Mode::Meter { .. }) => meter::NAME,
+        }
+    }
+}
+
 macro_rules! volume {
     ($dev:ident) => {
-        ($dev.volume.avg().0 * 100) / 0xffff
+        percent($dev.volume.avg())
     };
 }
 
-/// pulse operation which are sent to another thread to wait for
-type OpsMsgs = (
-    Vec<pulse::operation::Operation<dyn FnMut(ListResult<&SinkInfo<'_>>)>>,
-    Vec<pulse::operation::Operation<dyn FnMut(ListResult<&SourceInfo<'_>>)>>,
-);
+/// `volume` in percent of the normal volume, more than 100 if it's amplified
+fn percent(volume: Volume) -> u32 {
+    (volume.0 as f64 * 100.0 / Volume::NORMAL.0 as f64).round() as u32
+}
+
+/// Volume of one channel
+#[derive(Serialize)]
+struct Channel {
+    /// e.g. front-left
+    position: Option<String>,
+    volume: u32,
+}
+
+/// Volume of a sink or source in detail
+#[derive(Serialize)]
+struct Volumes {
+    channels: Vec<Channel>,
+    /// from -1.0 for only left to 1.0 for only right
+    balance: f32,
+    /// average volume in dB, `None` if silent or the device has no dB scale
+    volume_db: Option<f64>,
+    /// volume in percent at which the hardware runs at its own normal level,
+    /// `None` if the device has no dB scale
+    base_volume: Option<u32>,
+}
+
+impl Volumes {
+    /// `decibel` is the `DECIBEL_VOLUME` flag of the sink or source,
+    /// without it the volume can't be converted to dB and the base volume is meaningless
+    fn new(volume: &ChannelVolumes, map: &Map, base_volume: Volume, decibel: bool) -> Self {
+        Self {
+            channels: volume
+                .get()
+                .iter()
+                .zip(map.get())
+                .map(|(volume, position)| Channel {
+                    position: Position::to_string(*position).map(|name| name.into_owned()),
+                    volume: percent(*volume),
+                })
+                .collect(),
+            balance: volume.get_balance(map),
+            volume_db: Some(VolumeDB::from(volume.avg()).0).filter(|db| decibel && db.is_finite()),
+            base_volume: decibel.then(|| percent(base_volume)),
+        }
+    }
+}
+
+/// pulse operations which are sent to another thread to wait for
+type OpsMsgs = Vec<Box<dyn WaitOp + Send>>;
 
 #[derive(Debug)]
 enum WaitError {
@@ -80,7 +179,7 @@ struct Data {
 
 /// Sink/Source State,
 /// since libpulse_bindings doesn't implements Hash
-#[derive(Hash, Serialize)]
+#[derive(Clone, Copy, Hash, Serialize)]
 enum State {
     Invalid,
     Running,
@@ -109,17 +208,92 @@ impl From<pulse::def::SourceState> for State {
     }
 }
 
+/// Port of a sink or source, e.g. speakers or headphones
+#[derive(Serialize)]
+struct Port {
+    name: Option<String>,
+    /// e.g. Headphones
+    description: Option<String>,
+    priority: u32,
+    /// whether something is plugged in, `None` if the port can't tell
+    available: Option<bool>,
+}
+
+fn available(available: pulse::def::PortAvailable) -> Option<bool> {
+    match available {
+        pulse::def::PortAvailable::Unknown => None,
+        pulse::def::PortAvailable::No => Some(false),
+        pulse::def::PortAvailable::Yes => Some(true),
+    }
+}
+
+impl From<&SinkPortInfo<'_>> for Port {
+    fn from(port: &SinkPortInfo) -> Self {
+        Self {
+            name: port.name.clone().map(|name| name.into_owned()),
+            description: port.description.clone().map(|name| name.into_owned()),
+            priority: port.priority,
+            available: available(port.available),
+        }
+    }
+}
+impl From<&SourcePortInfo<'_>> for Port {
+    fn from(port: &SourcePortInfo) -> Self {
+        Self {
+            name: port.name.clone().map(|name| name.into_owned()),
+            description: port.description.clone().map(|name| name.into_owned()),
+            priority: port.priority,
+            available: available(port.available),
+        }
+    }
+}
+
+/// Properties of the hardware behind a sink or source
+#[derive(Serialize)]
+struct Device {
+    /// e.g. audio-headset-bluetooth
+    icon_name: Option<String>,
+    /// e.g. pci, usb or bluetooth
+    bus: Option<String>,
+    /// e.g. internal, speaker, headphone or headset
+    form_factor: Option<String>,
+    /// bluetooth codec in use, e.g. sbc, aac or ldac
+    codec: Option<String>,
+}
+
+impl From<&Proplist> for Device {
+    fn from(proplist: &Proplist) -> Self {
+        Self {
+            icon_name: proplist.get_str(properties::DEVICE_ICON_NAME),
+            bus: proplist.get_str(properties::DEVICE_BUS),
+            form_factor: proplist.get_str(properties::DEVICE_FORM_FACTOR),
+            // set on the bluetooth sink or source, pulseaudio and pipewire-pulse call it differently
+            codec: proplist
+                .get_str("bluetooth.codec")
+                .or_else(|| proplist.get_str("api.bluez5.codec")),
+        }
+    }
+}
+
 /// pulseAudio Sink representation
 /// since libpulse_bindings implements neither PartialEq nor Clone to store it in vec or hashset
 #[derive(Serialize)]
 struct Sink {
     name: String,
+    /// e.g. Built-in Audio Analog Stereo
+    description: Option<String>,
     index: u32,
     volume: u32,
+    #[serde(flatten)]
+    volumes: Volumes,
     muted: bool,
     monitor_index: u32,
     monitor_name: String,
     state: State,
+    active_port: Option<Port>,
+    ports: Vec<Port>,
+    #[serde(flatten)]
+    device: Device,
 }
 
 impl From<&SinkInfo<'_>> for Sink {
@@ -129,8 +303,15 @@ impl From<&SinkInfo<'_>> for Sink {
                 .name
                 .clone()
                 .map_or(String::from("Unknown"), |name| name.into_owned()),
+            description: sink.description.clone().map(|name| name.into_owned()),
             index: sink.index,
             volume: volume!(sink),
+            volumes: Volumes::new(
+                &sink.volume,
+                &sink.channel_map,
+                sink.base_volume,
+                sink.flags.contains(SinkFlagSet::DECIBEL_VOLUME),
+            ),
             muted: sink.mute || sink.volume.avg().is_muted(),
             monitor_index: sink.monitor_source,
             monitor_name: sink
@@ -138,6 +319,9 @@ impl From<&SinkInfo<'_>> for Sink {
                 .clone()
                 .map_or(String::from("Unknown"), |name| name.into_owned()),
             state: State::from(sink.state),
+            active_port: sink.active_port.as_deref().map(Port::from),
+            ports: sink.ports.iter().map(Port::from).collect(),
+            device: Device::from(&sink.proplist),
         }
     }
 }
@@ -159,12 +343,19 @@ impl Eq for Sink {}
 #[derive(Serialize)]
 struct Source {
     name: String,
+    description: Option<String>,
     index: u32,
     volume: u32,
+    #[serde(flatten)]
+    volumes: Volumes,
     muted: bool,
     monitor_index: Option<u32>,
     monitor_name: Option<String>,
     state: State,
+    active_port: Option<Port>,
+    ports: Vec<Port>,
+    #[serde(flatten)]
+    device: Device,
 }
 impl From<&SourceInfo<'_>> for Source {
     fn from(source: &SourceInfo) -> Self {
@@ -173,8 +364,15 @@ impl From<&SourceInfo<'_>> for Source {
                 .name
                 .clone()
                 .map_or(String::from("Unknown"), |name| name.into_owned()),
+            description: source.description.clone().map(|name| name.into_owned()),
             index: source.index,
             volume: volume!(source),
+            volumes: Volumes::new(
+                &source.volume,
+                &source.channel_map,
+                source.base_volume,
+                source.flags.contains(SourceFlagSet::DECIBEL_VOLUME),
+            ),
             muted: source.mute || source.volume.is_muted(),
             monitor_index: source.monitor_of_sink,
             monitor_name: source
@@ -182,6 +380,9 @@ impl From<&SourceInfo<'_>> for Source {
                 .clone()
                 .map(|name| name.into_owned()),
             state: State::from(source.state),
+            active_port: source.active_port.as_deref().map(Port::from),
+            ports: source.ports.iter().map(Port::from).collect(),
+            device: Device::from(&source.proplist),
         }
     }
 }
@@ -200,6 +401,188 @@ impl std::hash::Hash for Source {
     }
 }
 
+/// Profile of a card, e.g. A2DP or HSP/HFP for a bluetooth headset
+#[derive(Serialize)]
+struct Profile {
+    /// e.g. a2dp-sink or headset-head-unit
+    name: Option<String>,
+    /// e.g. High Fidelity Playback (A2DP Sink)
+    description: Option<String>,
+    /// number of sinks the card has with this profile
+    sinks: u32,
+    sources: u32,
+    priority: u32,
+    available: bool,
+}
+
+impl From<&CardProfileInfo<'_>> for Profile {
+    fn from(profile: &CardProfileInfo) -> Self {
+        Self {
+            name: profile.name.clone().map(|name| name.into_owned()),
+            description: profile.description.clone().map(|name| name.into_owned()),
+            sinks: profile.n_sinks,
+            sources: profile.n_sources,
+            priority: profile.priority,
+            available: profile.available,
+        }
+    }
+}
+
+/// Sound card, or bluetooth device, which has the sinks and sources
+#[derive(Serialize)]
+struct Card {
+    name: String,
+    description: Option<String>,
+    index: u32,
+    driver: Option<String>,
+    active_profile: Option<Profile>,
+    profiles: Vec<Profile>,
+    #[serde(flatten)]
+    device: Device,
+}
+
+impl From<&CardInfo<'_>> for Card {
+    fn from(card: &CardInfo) -> Self {
+        Self {
+            name: card
+                .name
+                .clone()
+                .map_or(String::from("Unknown"), |name| name.into_owned()),
+            description: card.proplist.get_str(properties::DEVICE_DESCRIPTION),
+            index: card.index,
+            driver: card.driver.clone().map(|name| name.into_owned()),
+            active_profile: card.active_profile.as_deref().map(Profile::from),
+            profiles: card.profiles.iter().map(Profile::from).collect(),
+            device: Device::from(&card.proplist),
+        }
+    }
+}
+
+impl PartialEq for Card {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl Eq for Card {}
+
+impl std::hash::Hash for Card {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+    }
+}
+
+/// Properties of the application a stream belongs to
+#[derive(Serialize, PartialEq)]
+struct Application {
+    /// e.g. Firefox
+    name: Option<String>,
+    /// e.g. firefox
+    binary: Option<String>,
+    icon_name: Option<String>,
+    process_id: Option<u32>,
+}
+
+impl From<&Proplist> for Application {
+    fn from(proplist: &Proplist) -> Self {
+        Self {
+            name: proplist.get_str(properties::APPLICATION_NAME),
+            binary: proplist.get_str(properties::APPLICATION_PROCESS_BINARY),
+            icon_name: proplist.get_str(properties::APPLICATION_ICON_NAME),
+            process_id: proplist
+                .get_str(properties::APPLICATION_PROCESS_ID)
+                .and_then(|id| id.parse().ok()),
+        }
+    }
+}
+
+/// Stream of an application playing to a sink
+#[derive(Serialize)]
+struct SinkInput {
+    /// what's playing, e.g. the title of the video
+    name: Option<String>,
+    index: u32,
+    application: Application,
+    volume: u32,
+    muted: bool,
+    /// paused by the application
+    corked: bool,
+    /// index of the sink it plays to
+    sink: u32,
+}
+
+impl From<&SinkInputInfo<'_>> for SinkInput {
+    fn from(input: &SinkInputInfo) -> Self {
+        Self {
+            name: input.name.clone().map(|name| name.into_owned()),
+            index: input.index,
+            application: Application::from(&input.proplist),
+            volume: volume!(input),
+            muted: input.mute || input.volume.is_muted(),
+            corked: input.corked,
+            sink: input.sink,
+        }
+    }
+}
+
+impl PartialEq for SinkInput {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl Eq for SinkInput {}
+
+impl std::hash::Hash for SinkInput {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+    }
+}
+
+/// Stream of an application recording from a source
+#[derive(Serialize)]
+struct SourceOutput {
+    name: Option<String>,
+    index: u32,
+    application: Application,
+    volume: u32,
+    muted: bool,
+    corked: bool,
+    /// only measures the level, like the meters of pavucontrol
+    peak_detect: bool,
+    /// index of the source it records from
+    source: u32,
+}
+
+impl From<&SourceOutputInfo<'_>> for SourceOutput {
+    fn from(output: &SourceOutputInfo) -> Self {
+        Self {
+            name: output.name.clone().map(|name| name.into_owned()),
+            index: output.index,
+            application: Application::from(&output.proplist),
+            volume: volume!(output),
+            muted: output.mute || output.volume.is_muted(),
+            corked: output.corked,
+            peak_detect: output.resample_method.as_deref() == Some("peaks"),
+            source: output.source,
+        }
+    }
+}
+
+impl PartialEq for SourceOutput {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl Eq for SourceOutput {}
+
+impl std::hash::Hash for SourceOutput {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+    }
+}
+
 pub struct Connection {
     cnxt: Context,
     mnlp: Mainloop,
@@ -213,6 +596,11 @@ struct Information {
     default_sink: Option<Sink>,
     /// default source index
     default_source: Option<Source>,
+    /// streams of applications playing
+    sink_inputs: HashSet<SinkInput>,
+    /// streams of applications recording
+    source_outputs: HashSet<SourceOutput>,
+    cards: HashSet<Card>,
 }
 
 fn reset(info: &Arc<Mutex<Information>>) {
@@ -221,53 +609,83 @@ fn reset(info: &Arc<Mutex<Information>>) {
     ilock.sources.clear();
     ilock.default_sink.take();
     ilock.default_source.take();
+    ilock.sink_inputs.clear();
+    ilock.source_outputs.clear();
+    ilock.cards.clear();
 }
 
 fn refill_info(
     info: &Arc<Mutex<Information>>,
     intr: &pulse::context::introspect::Introspector,
 ) -> OpsMsgs {
-    let mut src_ops = Vec::with_capacity(4);
-    let mut sink_ops = Vec::with_capacity(4);
+    let mut ops: OpsMsgs = Vec::with_capacity(7);
     let iclone = Arc::clone(info);
-    sink_ops.push(intr.get_sink_info_list(move |res| {
+    ops.push(Box::new(intr.get_sink_info_list(move |res| {
         let ListResult::Item(sink) = res else { return };
         let mut ilock = iclone.lock().unwrap();
         ilock.sinks.insert(Sink::from(sink));
-    }));
+    })));
     let iclone = Arc::clone(info);
-    src_ops.push(intr.get_source_info_list(move |res| {
+    ops.push(Box::new(intr.get_source_info_list(move |res| {
         let ListResult::Item(source) = res else {
             return;
         };
         let mut ilock = iclone.lock().unwrap();
         ilock.sources.insert(Source::from(source));
-    }));
+    })));
+    let iclone = Arc::clone(info);
+    ops.push(Box::new(intr.get_sink_input_info_list(move |res| {
+        let ListResult::Item(input) = res else { return };
+        let mut ilock = iclone.lock().unwrap();
+        ilock.sink_inputs.insert(SinkInput::from(input));
+    })));
+    let iclone = Arc::clone(info);
+    ops.push(Box::new(intr.get_source_output_info_list(move |res| {
+        let ListResult::Item(output) = res else {
+            return;
+        };
+        let mut ilock = iclone.lock().unwrap();
+        ilock.source_outputs.insert(SourceOutput::from(output));
+    })));
+    let iclone = Arc::clone(info);
+    ops.push(Box::new(intr.get_card_info_list(move |res| {
+        let ListResult::Item(card) = res else { return };
+        let mut ilock = iclone.lock().unwrap();
+        ilock.cards.insert(Card::from(card));
+    })));
 
     let iclone = Arc::clone(info);
-    sink_ops.push(intr.get_sink_info_by_name("@DEFAULT_SINK@", move |list| {
-        if let pulse::callbacks::ListResult::Item(sink) = list {
-            iclone.lock().unwrap().default_sink = Some(Sink::from(sink));
-        }
-    }));
+    ops.push(Box::new(intr.get_sink_info_by_name(
+        "@DEFAULT_SINK@",
+        move |list| {
+            if let pulse::callbacks::ListResult::Item(sink) = list {
+                iclone.lock().unwrap().default_sink = Some(Sink::from(sink));
+            }
+        },
+    )));
     let iclone = Arc::clone(info);
-    src_ops.push(
-        intr.get_source_info_by_name("@DEFAULT_SOURCE@", move |list| {
+    ops.push(Box::new(intr.get_source_info_by_name(
+        "@DEFAULT_SOURCE@",
+        move |list| {
             if let pulse::callbacks::ListResult::Item(source) = list {
                 iclone.lock().unwrap().default_source = Some(source.into());
             }
-        }),
-    );
-    (sink_ops, src_ops)
+        },
+    )));
+    ops
 }
 
 impl Connection {
     fn new(timeout: u64) -> Result<Self> {
+        Self::to(None, timeout)
+    }
+    /// Connects to `server`, e.g. `unix:/run/user/1000/pulse/native`, or the default one
+    fn to(server: Option<&str>, timeout: u64) -> Result<Self> {
         let mnlp = Mainloop::new().unwrap();
         for _ in 0..10 {
             let mut cnxt = Context::new(&mnlp, "pfui_listener").unwrap();
             if cnxt
-                .connect(None, pulse::context::FlagSet::NOAUTOSPAWN, None)
+                .connect(server, pulse::context::FlagSet::NOAUTOSPAWN, None)
                 .is_ok()
             {
                 return Ok(Self { cnxt, mnlp });
@@ -300,7 +718,30 @@ impl Connection {
     }
 }
 
-pub struct PulseAudio {}
+/// Prints everything, or only what the privacy indicator needs
+fn output(privacy: bool, info: &Information, cameras: &[Application]) {
+    if privacy {
+        crate::print(privacy::NAME, &Some(privacy::Privacy::new(info, cameras)))
+    } else {
+        crate::print(NAME, &Some(info))
+    }
+}
+
+pub struct PulseAudio {
+    mode: Option<Mode>,
+    #[cfg(feature = "pipewire")]
+    pipewire: bool,
+}
+
+impl PulseAudio {
+    pub fn new(opts: &Opts) -> Self {
+        Self {
+            mode: opts.mode.clone(),
+            #[cfg(feature = "pipewire")]
+            pipewire: opts.pipewire,
+        }
+    }
+}
 
 impl Module for PulseAudio {
     type Connection = Connection;
@@ -309,13 +750,29 @@ impl Module for PulseAudio {
     }
 
     fn start(&mut self, timeout: u64) -> Result<()> {
+        #[cfg(feature = "pipewire")]
+        if self.pipewire {
+            if self.mode.is_some() {
+                return Err(crate::Fatal(String::from(
+                    "privacy and meter need pulseaudio, they don't work with --pipewire",
+                ))
+                .into());
+            }
+            return pipewire::start();
+        }
         let mut conn = self.connect(timeout)?;
         if conn.connect().is_err() {
             return Err(anyhow!("Error establishing connection"));
         }
-        let interest = pulse::context::subscribe::InterestMaskSet::SINK
-            | pulse::context::subscribe::InterestMaskSet::SOURCE
-            | pulse::context::subscribe::InterestMaskSet::SERVER;
+        if let Some(Mode::Meter { source, frequency }) = &self.mode {
+            return meter::start(conn, source.as_deref(), *frequency);
+        }
+        let interest = InterestMaskSet::SINK
+            | InterestMaskSet::SOURCE
+            | InterestMaskSet::SINK_INPUT
+            | InterestMaskSet::SOURCE_OUTPUT
+            | InterestMaskSet::CARD
+            | InterestMaskSet::SERVER;
         conn.cnxt.subscribe(interest, |_| {});
         // print the data for initialization
         // sources and sinks
@@ -324,18 +781,30 @@ impl Module for PulseAudio {
             std::sync::mpsc::Sender<OpsMsgs>,
             std::sync::mpsc::Receiver<OpsMsgs>,
         ) = std::sync::mpsc::channel();
+        let privacy = matches!(self.mode, Some(Mode::Privacy));
+        let cameras = Arc::new(Mutex::new(Vec::new()));
+        if privacy {
+            let devices = Arc::downgrade(&devices);
+            let cclone = Arc::clone(&cameras);
+            privacy::watch_cameras(Arc::clone(&cameras), move || {
+                let Some(devices) = devices.upgrade() else {
+                    return false;
+                };
+                let dlock = devices.lock().unwrap();
+                output(privacy, &dlock, &cclone.lock().unwrap());
+                true
+            })?;
+        }
         let dclone = Arc::clone(&devices);
         // had to create separate thread for waiting for operations to finish, in call back if we wait then they will be
         // blocked forever. If we don't wait for them then Information printed will be of last operation, i.e. until
         // the event call back is not finished othercallbacks requesting information won't get executed. This is fine if
         // the volume differs by marginal but won't work for mute/unmute that will show exact opposite, so had to move it to another thread
         std::thread::spawn(move || {
-            for msg in rx.iter() {
-                let (sink_ops, src_ops) = msg;
-                sink_ops.iter().for_each(|op| op.wait());
-                src_ops.iter().for_each(|op| op.wait());
+            for ops in rx.iter() {
+                ops.iter().for_each(|op| op.wait());
                 let dlock = dclone.lock().unwrap();
-                crate::print(&Some(std::ops::Deref::deref(&dlock)));
+                output(privacy, &dlock, &cameras.lock().unwrap());
             }
         });
         let introspector = conn.cnxt.introspect();
@@ -343,8 +812,7 @@ impl Module for PulseAudio {
 
         conn.cnxt
             .set_subscribe_callback(Some(Box::new(move |facility, operation, index| {
-                let mut sink_ops = Vec::with_capacity(4);
-                let mut src_ops = Vec::with_capacity(4);
+                let mut ops: OpsMsgs = Vec::with_capacity(4);
                 let Some(operation) = operation else {
                     return;
                 };
@@ -352,69 +820,110 @@ impl Module for PulseAudio {
                     return;
                 };
                 let device_c = Arc::clone(&devices);
-                sink_ops.push(introspector.get_sink_info_by_name("@DEFAULT_SINK@", move |list| {
-                    if let pulse::callbacks::ListResult::Item(sink) = list {
-                        device_c.lock().unwrap().default_sink = Some(sink.into());
-                    }
-                }));
+                ops.push(Box::new(introspector.get_sink_info_by_name(
+                    "@DEFAULT_SINK@",
+                    move |list| {
+                        if let pulse::callbacks::ListResult::Item(sink) = list {
+                            device_c.lock().unwrap().default_sink = Some(sink.into());
+                        }
+                    },
+                )));
                 let device_c = Arc::clone(&devices);
-                src_ops.push(introspector.get_source_info_by_name("@DEFAULT_SOURCE@", move |list| {
-                    if let pulse::callbacks::ListResult::Item(source) = list {
-                        device_c.lock().unwrap().default_source = Some(source.into());
+                ops.push(Box::new(introspector.get_source_info_by_name(
+                    "@DEFAULT_SOURCE@",
+                    move |list| {
+                        if let pulse::callbacks::ListResult::Item(source) = list {
+                            device_c.lock().unwrap().default_source = Some(source.into());
+                        }
+                    },
+                )));
+                match (operation, facility) {
+                    // the server has changed, may be default sink / source has changed, that's updated already
+                    (_, Facility::Server) => (),
+                    // streams come and go all the time, so they're removed one by one
+                    (Operation::Removed, Facility::SinkInput) => {
+                        devices
+                            .lock()
+                            .unwrap()
+                            .sink_inputs
+                            .retain(|input| input.index != index);
+                    }
+                    (Operation::Removed, Facility::SourceOutput) => {
+                        devices
+                            .lock()
+                            .unwrap()
+                            .source_outputs
+                            .retain(|output| output.index != index);
+                    }
+                    (Operation::Removed, _) => {
+                        reset(&devices);
+                        ops = refill_info(&devices, &introspector);
+                    }
+                    // replace inserts as well, so new and changed are handled the same
+                    (_, Facility::Sink) => {
+                        let dclone = devices.clone();
+                        ops.push(Box::new(introspector.get_sink_info_by_index(
+                            index,
+                            move |res| {
+                                let ListResult::Item(sink) = res else { return };
+                                let mut dlock = dclone.lock().unwrap();
+                                dlock.sinks.replace(Sink::from(sink));
+                            },
+                        )));
                     }
-                }));
-                match operation {
-                    pulse::context::subscribe::Operation::New => {
-                        match facility{
-                            pulse::context::subscribe::Facility::Sink => {
-                                let dclone = devices.clone();
-                                sink_ops.push(introspector.get_sink_info_by_index(index, move |res|{
-                                    let ListResult::Item(sink) = res else{ return};
-                                    let mut dlock = dclone.lock().unwrap();
-                                    dlock.sinks.insert(Sink::from(sink));
-                                }));
+                    (_, Facility::Source) => {
+                        let dclone = devices.clone();
+                        ops.push(Box::new(introspector.get_source_info_by_index(
+                            index,
+                            move |res| {
+                                let ListResult::Item(source) = res else {
+                                    return;
+                                };
+                                let mut dlock = dclone.lock().unwrap();
+                                dlock.sources.replace(Source::from(source));
                             },
-                            pulse::context::subscribe::Facility::Source => {
-                                let dclone = devices.clone();
-                                src_ops.push(introspector.get_source_info_by_index(index, move |res|{
-                                    let ListResult::Item(source) = res else{ return};
-                                    let mut dlock = dclone.lock().unwrap();
-                                    dlock.sources.insert(Source::from(source));
-                                }));
+                        )));
+                    }
+                    (_, Facility::SinkInput) => {
+                        let dclone = devices.clone();
+                        ops.push(Box::new(introspector.get_sink_input_info(
+                            index,
+                            move |res| {
+                                let ListResult::Item(input) = res else { return };
+                                let mut dlock = dclone.lock().unwrap();
+                                dlock.sink_inputs.replace(SinkInput::from(input));
                             },
-                            _ => eprintln!("{facility:?} is not handled when inserted, This was not supposed to enabled also"),
-                        };
-                    },
-                    pulse::context::subscribe::Operation::Changed => {
-                        match facility{
-                            pulse::context::subscribe::Facility::Sink => {
-                                let dclone = devices.clone();
-                                sink_ops.push(introspector.get_sink_info_by_index(index, move |res|{
-                                    let ListResult::Item(sink) = res else{ return};
-                                    let mut dlock = dclone.lock().unwrap();
-                                    dlock.sinks.replace(Sink::from(sink));
-                                }));
-
+                        )));
+                    }
+                    (_, Facility::SourceOutput) => {
+                        let dclone = devices.clone();
+                        ops.push(Box::new(introspector.get_source_output_info(
+                            index,
+                            move |res| {
+                                let ListResult::Item(output) = res else {
+                                    return;
+                                };
+                                let mut dlock = dclone.lock().unwrap();
+                                dlock.source_outputs.replace(SourceOutput::from(output));
                             },
-                            pulse::context::subscribe::Facility::Source => {
-                                let dclone = devices.clone();
-                                src_ops.push(introspector.get_source_info_by_index(index, move |res|{
-                                    let ListResult::Item(source) = res else{ return};
-                                    let mut dlock = dclone.lock().unwrap();
-                                    dlock.sources.replace(Source::from(source));
-                                }));
+                        )));
+                    }
+                    (_, Facility::Card) => {
+                        let dclone = devices.clone();
+                        ops.push(Box::new(introspector.get_card_info_by_index(
+                            index,
+                            move |res| {
+                                let ListResult::Item(card) = res else { return };
+                                let mut dlock = dclone.lock().unwrap();
+                                dlock.cards.replace(Card::from(card));
                             },
-                            // if the server has changed may be default sink / source has changed, just update it
-                            pulse::context::subscribe::Facility::Server => (),
-                            _ => panic!("We are not expecting {facility:?}, this was supposed to be masked"),
-                        }
-                    },
-                    pulse::context::subscribe::Operation::Removed => {
-                        reset(&devices);
-                        (sink_ops, src_ops) = refill_info(&devices, &introspector);
-                    },
+                        )));
+                    }
+                    _ => {
+                        panic!("We are not expecting {facility:?}, this was supposed to be masked")
+                    }
                 }
-                tx.send((sink_ops, src_ops)).unwrap();
+                tx.send(ops).unwrap();
             })));
         match conn.mnlp.run() {
             Ok(_retval) => Ok(()),
@@ -425,3 +934,40 @@ impl Module for PulseAudio {
     #[allow(unused)]
     fn output(&self, conn: &mut Self::Connection) {}
 }
+
+/// Actions of `pfui ctl pulseaudio`
+#[derive(Subcommand)]
+pub enum Action {
+    #[command(about = "switch the profile of a card, given by name or index")]
+    Profile { card: String, profile: String },
+}
+
+impl Action {
+    pub fn run(&self) -> Result<()> {
+        let mut conn = Connection::new(1)?;
+        conn.connect()
+            .map_err(|e| anyhow!("Failed to connect to pulseaudio: {e}"))?;
+        let mut introspector = conn.cnxt.introspect();
+        match self {
+            Action::Profile { card, profile } => {
+                let success = Rc::new(Cell::new(false));
+                let sclone = Rc::clone(&success);
+                let callback: Box<dyn FnMut(bool)> = Box::new(move |ok| sclone.set(ok));
+                let op = match card.parse() {
+                    Ok(index) => {
+                        introspector.set_card_profile_by_index(index, profile, Some(callback))
+                    }
+                    Err(_) => introspector.set_card_profile_by_name(card, profile, Some(callback)),
+                };
+                op.wait_with_loop(&mut conn.mnlp).map_err(|e| match e {
+                    WaitError::Quit => anyhow!("Lost the connection to pulseaudio"),
+                    WaitError::Error(e) => anyhow::Error::new(e),
+                })?;
+                if !success.get() {
+                    bail!("Failed to switch card {card} to profile {profile}");
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+//! Level of what's playing, or of a source, for a VU meter in the bar. Like the meters of
+//! pavucontrol it records with peak detection, so the server sends only one peak per interval.
+use anyhow::{anyhow, bail, Result};
+use pulse::{
+    callbacks::ListResult,
+    context::{
+        subscribe::{Facility, InterestMaskSet},
+        Context,
+    },
+    def::BufferAttr,
+    mainloop::standard::IterateResult,
+    sample::{Format, Spec},
+    stream::{FlagSet, PeekResult, Stream},
+};
+use serde::Serialize;
+use std::{
+    cell::{Cell, RefCell},
+    rc::{Rc, Weak},
+};
+
+use super::Connection;
+
+pub const NAME: &str = "pulseaudio-meter";
+
+/// the stream and the name of the source it records
+type Recording = Option<(Rc<RefCell<Stream>>, String)>;
+
+#[derive(Serialize)]
+struct Meter<'a> {
+    /// from 0.0 to 1.0
+    peak: f32,
+    /// name of the source recorded
+    source: &'a str,
+}
+
+fn print(peak: f32, source: &str) {
+    // two digits are plenty for a bar, and the same level isn't printed twice
+    let peak = (peak.clamp(0.0, 1.0) * 100.0).round() / 100.0;
+    crate::print(NAME, &Some(Meter { peak, source }));
+}
+
+/// The loudest of the peaks in `data`, several intervals can arrive at once
+fn peak(data: &[u8]) -> Option<f32> {
+    data.chunks_exact(4)
+        .map(|sample| f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]))
+        .reduce(f32::max)
+}
+
+/// Reads the peaks arriving on `stream`
+fn read(stream: &Weak<RefCell<Stream>>, source: &str) {
+    let Some(stream) = stream.upgrade() else {
+        return;
+    };
+    let mut stream = stream.borrow_mut();
+    let peak = match stream.peek() {
+        Ok(PeekResult::Data(data)) => peak(data),
+        Ok(PeekResult::Hole(_)) => None,
+        Ok(PeekResult::Empty) | Err(_) => return,
+    };
+    let _ = stream.discard();
+    if let Some(peak) = peak {
+        print(peak, source);
+    }
+}
+
+/// Starts recording the peaks of `source`. The stream stops when it's dropped.
+fn record(
+    cnxt: &mut Context,
+    source: &str,
+    frequency: u32,
+    failed: &Rc<Cell<bool>>,
+) -> Result<Rc<RefCell<Stream>>> {
+    // one float per interval, which is the peak of that interval
+    let spec = Spec {
+        format: Format::F32le,
+        channels: 1,
+        rate: frequency,
+    };
+    if !spec.is_valid() {
+        return Err(crate::Fatal(format!("Invalid meter frequency {frequency}")).into());
+    }
+    let stream = Stream::new(cnxt, "pfui meter", &spec, None)
+        .ok_or_else(|| anyhow!("Failed to create the meter stream"))?;
+    let stream = Rc::new(RefCell::new(stream));
+    let weak = Rc::downgrade(&stream);
+    let name = source.to_owned();
+    stream
+        .borrow_mut()
+        .set_read_callback(Some(Box::new(move |_| read(&weak, &name))));
+    // a suspended source sends nothing, the last peak would stay forever
+    let weak = Rc::downgrade(&stream);
+    let name = source.to_owned();
+    stream
+        .borrow_mut()
+        .set_suspended_callback(Some(Box::new(move || {
+            let Some(stream) = weak.upgrade() else {
+                return;
+            };
+            if stream.borrow().is_suspended() == Ok(true) {
+                print(0.0, &name);
+            }
+        })));
+    let weak = Rc::downgrade(&stream);
+    let fclone = Rc::clone(failed);
+    stream
+        .borrow_mut()
+        .set_state_callback(Some(Box::new(move || {
+            let Some(stream) = weak.upgrade() else {
+                return;
+            };
+            // with DONT_MOVE the stream fails when its source goes away
+            let state = stream.try_borrow().map(|stream| stream.get_state());
+            if matches!(state, Ok(pulse::stream::State::Failed)) {
+                fclone.set(true);
+            }
+        })));
+    let attr = BufferAttr {
+        maxlength: u32::MAX,
+        tlength: u32::MAX,
+        prebuf: u32::MAX,
+        minreq: u32::MAX,
+        fragsize: std::mem::size_of::<f32>() as u32,
+    };
+    stream.borrow_mut().connect_record(
+        Some(source),
+        Some(&attr),
+        FlagSet::PEAK_DETECT
+            | FlagSet::ADJUST_LATENCY
+            | FlagSet::DONT_MOVE
+            | FlagSet::DONT_INHIBIT_AUTO_SUSPEND,
+    )?;
+    Ok(stream)
+}
+
+/// Prints the peaks of `source`, or of the monitor of the default sink, `frequency` times a second.
+/// The default sink is followed when it changes.
+pub(super) fn start(conn: Connection, source: Option<&str>, frequency: u32) -> Result<()> {
+    let Connection { cnxt, mut mnlp } = conn;
+    let cnxt = Rc::new(RefCell::new(cnxt));
+    let failed = Rc::new(Cell::new(false));
+    let current: Rc<RefCell<Recording>> = Rc::default();
+    if let Some(source) = source {
+        let stream = record(&mut cnxt.borrow_mut(), source, frequency, &failed)?;
+        *current.borrow_mut() = Some((stream, source.to_owned()));
+    } else {
+        let introspector = cnxt.borrow().introspect();
+        // the context keeps the callback, strong references would keep the context forever
+        let (cweak, sweak, fclone) = (
+            Rc::downgrade(&cnxt),
+            Rc::downgrade(&current),
+            Rc::clone(&failed),
+        );
+        // looks up the monitor of the default sink, and records it if it's not recorded already
+        let follow = move || {
+            let (cweak, sweak, failed) = (cweak.clone(), sweak.clone(), Rc::clone(&fclone));
+            introspector.get_sink_info_by_name("@DEFAULT_SINK@", move |res| {
+                let ListResult::Item(sink) = res else { return };
+                let Some(monitor) = sink.monitor_source_name.as_deref() else {
+                    return;
+                };
+                let (Some(cnxt), Some(current)) = (cweak.upgrade(), sweak.upgrade()) else {
+                    return;
+                };
+                if matches!(&*current.borrow(), Some((_, source)) if source == monitor) {
+                    return;
+                }
+                if let Some((stream, _)) = current.borrow_mut().take() {
+                    let _ = stream.borrow_mut().disconnect();
+                }
+                let recorded = record(&mut cnxt.borrow_mut(), monitor, frequency, &failed);
+                match recorded {
+                    Ok(stream) => *current.borrow_mut() = Some((stream, monitor.to_owned())),
+                    Err(e) => {
+                        log::warn!("Failed to record {monitor}: {e:#}");
+                        failed.set(true);
+                    }
+                }
+            });
+        };
+        follow();
+        // the default sink changing is a change of the server
+        let mut cnxt = cnxt.borrow_mut();
+        cnxt.subscribe(InterestMaskSet::SERVER, |_| {});
+        cnxt.set_subscribe_callback(Some(Box::new(move |facility, _, _| {
+            if facility == Some(Facility::Server) {
+                follow();
+            }
+        })));
+    }
+    loop {
+        match mnlp.iterate(true) {
+            IterateResult::Quit(_) => bail!("pulseaudio mainloop quit"),
+            IterateResult::Err(e) => return Err(e.into()),
+            IterateResult::Success(_) => {}
+        }
+        if failed.get() {
+            bail!("Lost the meter stream");
+        }
+        if let Ok(cnxt) = cnxt.try_borrow() {
+            if matches!(
+                cnxt.get_state(),
+                pulse::context::State::Failed | pulse::context::State::Terminated
+            ) {
+                bail!("Lost the connection to pulseaudio");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loudest_peak() {
+        let data: Vec<u8> = [0.2f32, 0.9, 0.4]
+            .iter()
+            .flat_map(|peak| peak.to_le_bytes())
+            .collect();
+        assert_eq!(peak(&data), Some(0.9));
+        // a partial sample is ignored
+        assert_eq!(peak(&data[..6]), Some(0.2));
+        assert_eq!(peak(&[]), None);
+    }
+
+    #[test]
+    fn frequencies() {
+        let meter = |frequency: &str| {
+            crate::Modules::from_spec(&format!("pulseaudio meter --frequency {frequency}"))
+        };
+        assert!(meter("1").is_ok() && meter("1000").is_ok());
+        assert!(meter("0").is_err() && meter("1001").is_err());
+    }
+
+    /// Private pulseaudio server with only a null sink, killed when dropped
+    struct Server {
+        child: std::process::Child,
+        dir: std::path::PathBuf,
+    }
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Starts `pulseaudio` with a null sink `pfui_test`, `None` if there is none to start
+    fn null_sink() -> Option<(Server, String)> {
+        let dir = std::env::temp_dir().join(format!("pfui-pulse-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).ok()?;
+        let socket = dir.join("native");
+        let child = std::process::Command::new("pulseaudio")
+            .args([
+                "-n",
+                "--daemonize=no",
+                "--exit-idle-time=-1",
+                "--use-pid-file=no",
+            ])
+            .arg(format!(
+                "--load=module-native-protocol-unix socket={} auth-anonymous=1",
+                socket.display()
+            ))
+            .arg("--load=module-null-sink sink_name=pfui_test")
+            .env("HOME", &dir)
+            .env("XDG_RUNTIME_DIR", &dir)
+            .env("XDG_CONFIG_HOME", &dir)
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .ok()?;
+        let server = Server { child, dir };
+        for _ in 0..50 {
+            if socket.exists() {
+                return Some((server, format!("unix:{}", socket.display())));
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        None
+    }
+
+    /// Outputs of the meter
+    struct Peaks(std::sync::mpsc::Sender<serde_json::Value>);
+
+    impl crate::hub::Sink for Peaks {
+        fn publish(&mut self, module: &str, output: &serde_json::Value) {
+            if module == NAME {
+                let _ = self.0.send(output.clone());
+            }
+        }
+    }
+
+    #[test]
+    fn records_the_default_sink_of_a_null_sink() {
+        let Some((server, address)) = null_sink() else {
+            eprintln!("no pulseaudio, skipping");
+            return;
+        };
+        let (tx, rx) = std::sync::mpsc::channel();
+        let sink = crate::hub::add_sink(Peaks(tx));
+        let (done, stopped) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let result = Connection::to(Some(&address), 0).and_then(|mut conn| {
+                conn.connect().map_err(|e| anyhow!("{e}"))?;
+                start(conn, None, 10)
+            });
+            let _ = done.send(result);
+        });
+        let timeout = std::time::Duration::from_secs(5);
+        let output = rx.recv_timeout(timeout).unwrap();
+        assert_eq!(output["ok"], 1);
+        assert_eq!(output["data"]["source"], "pfui_test.monitor");
+        // the meter stops once the server is gone, so it can be restarted
+        drop(server);
+        assert!(stopped.recv_timeout(timeout).unwrap().is_err());
+        crate::hub::remove_sink(sink);
+    }
+}
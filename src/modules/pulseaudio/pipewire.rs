@@ -0,0 +1,524 @@
+//! Sinks, sources and streams read from the PipeWire registry instead of through pipewire-pulse.
+//! The output is the same as with pulseaudio, as far as PipeWire has the same information:
+//! ports and cards aren't known, they're properties of the devices rather than of the nodes.
+use anyhow::{bail, Result};
+use pipewire::{
+    self as pw,
+    context::Context,
+    main_loop::MainLoop,
+    metadata::{Metadata, MetadataListener},
+    node::{Node, NodeListener, NodeState},
+    registry::GlobalObject,
+    spa::{
+        self,
+        param::ParamType,
+        pod::{deserialize::PodDeserializer, Pod, Value, ValueArray},
+        utils::dict::DictRef,
+    },
+    types::ObjectType,
+};
+use serde::Deserialize;
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
+
+use super::{
+    Application, Channel, Device, Information, Sink, SinkInput, Source, SourceOutput, State,
+    Volumes, NAME,
+};
+
+/// The kinds of nodes which are printed, by their media.class
+#[derive(Clone, Copy, PartialEq)]
+enum Class {
+    Sink,
+    Source,
+    SinkInput,
+    SourceOutput,
+}
+
+impl Class {
+    fn new(media_class: &str) -> Option<Self> {
+        match media_class {
+            "Audio/Sink" | "Audio/Duplex" => Some(Self::Sink),
+            "Audio/Source" | "Audio/Source/Virtual" => Some(Self::Source),
+            "Stream/Output/Audio" => Some(Self::SinkInput),
+            "Stream/Input/Audio" => Some(Self::SourceOutput),
+            _ => None,
+        }
+    }
+}
+
+/// What's known of a node, from its properties, info and Props param
+struct Audio {
+    class: Class,
+    props: HashMap<String, String>,
+    state: State,
+    /// linear, 1.0 is the normal volume
+    volumes: Vec<f32>,
+    /// spa audio channel of each volume
+    positions: Vec<u32>,
+    muted: bool,
+    /// linear volume at which the hardware runs at its own level, if the node tells
+    base_volume: Option<f32>,
+}
+
+/// value of the default.audio.sink and default.audio.source metadata
+#[derive(Deserialize)]
+struct DefaultNode {
+    name: String,
+}
+
+#[derive(Default)]
+struct Graph {
+    nodes: HashMap<u32, Audio>,
+    /// output node and input node of every link
+    links: HashMap<u32, (u32, u32)>,
+    default_sink: Option<String>,
+    default_source: Option<String>,
+}
+
+/// Name of a spa audio channel like pulseaudio calls it
+fn position(channel: u32) -> Option<&'static str> {
+    use spa::sys::*;
+    Some(match channel {
+        SPA_AUDIO_CHANNEL_MONO => "mono",
+        SPA_AUDIO_CHANNEL_FL => "front-left",
+        SPA_AUDIO_CHANNEL_FR => "front-right",
+        SPA_AUDIO_CHANNEL_FC => "front-center",
+        SPA_AUDIO_CHANNEL_LFE => "lfe",
+        SPA_AUDIO_CHANNEL_SL => "side-left",
+        SPA_AUDIO_CHANNEL_SR => "side-right",
+        SPA_AUDIO_CHANNEL_FLC => "front-left-of-center",
+        SPA_AUDIO_CHANNEL_FRC => "front-right-of-center",
+        SPA_AUDIO_CHANNEL_RC => "rear-center",
+        SPA_AUDIO_CHANNEL_RL => "rear-left",
+        SPA_AUDIO_CHANNEL_RR => "rear-right",
+        SPA_AUDIO_CHANNEL_TC => "top-center",
+        SPA_AUDIO_CHANNEL_TFL => "top-front-left",
+        SPA_AUDIO_CHANNEL_TFC => "top-front-center",
+        SPA_AUDIO_CHANNEL_TFR => "top-front-right",
+        SPA_AUDIO_CHANNEL_TRL => "top-rear-left",
+        SPA_AUDIO_CHANNEL_TRC => "top-rear-center",
+        SPA_AUDIO_CHANNEL_TRR => "top-rear-right",
+        _ => return None,
+    })
+}
+
+/// PipeWire volumes are linear, pulseaudio's are cubic: 0.125 is 50%
+fn percent(volume: f32) -> f64 {
+    f64::from(volume.max(0.0)).cbrt() * 100.0
+}
+
+impl Audio {
+    fn prop(&self, key: &str) -> Option<String> {
+        self.props.get(key).cloned()
+    }
+
+    fn name(&self) -> String {
+        self.prop("node.name")
+            .unwrap_or_else(|| String::from("Unknown"))
+    }
+
+    /// average of the channels in percent
+    fn volume(&self) -> f64 {
+        if self.volumes.is_empty() {
+            return 0.0;
+        }
+        self.volumes.iter().map(|v| percent(*v)).sum::<f64>() / self.volumes.len() as f64
+    }
+
+    fn volumes(&self) -> Volumes {
+        let side = |names: &[&str]| {
+            let volumes: Vec<f64> = self
+                .volumes
+                .iter()
+                .zip(&self.positions)
+                .filter(|(_, channel)| {
+                    position(**channel).is_some_and(|name| names.contains(&name))
+                })
+                .map(|(volume, _)| percent(*volume))
+                .collect();
+            (!volumes.is_empty()).then(|| volumes.iter().sum::<f64>() / volumes.len() as f64)
+        };
+        let left = side(&[
+            "front-left",
+            "side-left",
+            "rear-left",
+            "front-left-of-center",
+        ]);
+        let right = side(&[
+            "front-right",
+            "side-right",
+            "rear-right",
+            "front-right-of-center",
+        ]);
+        // the same as pulseaudio's pa_cvolume_get_balance
+        let balance = match (left, right) {
+            (Some(left), Some(right)) if left != right => {
+                if left > right {
+                    right / left - 1.0
+                } else {
+                    1.0 - left / right
+                }
+            }
+            _ => 0.0,
+        };
+        Volumes {
+            channels: self
+                .volumes
+                .iter()
+                .zip(
+                    self.positions
+                        .iter()
+                        .map(Some)
+                        .chain(std::iter::repeat(None)),
+                )
+                .map(|(volume, channel)| Channel {
+                    position: channel.and_then(|c| position(*c)).map(String::from),
+                    volume: percent(*volume).round() as u32,
+                })
+                .collect(),
+            balance: balance as f32,
+            // the volumes are linear factors, so the dB are known once the node sent them
+            volume_db: Some(60.0 * (self.volume() / 100.0).log10()).filter(|db| db.is_finite()),
+            base_volume: self.base_volume.map(|base| percent(base).round() as u32),
+        }
+    }
+
+    fn device(&self) -> Device {
+        Device {
+            icon_name: self.prop("device.icon-name"),
+            bus: self.prop("device.bus"),
+            form_factor: self.prop("device.form-factor"),
+            codec: self.prop("api.bluez5.codec"),
+        }
+    }
+
+    fn application(&self) -> Application {
+        Application {
+            name: self.prop("application.name"),
+            binary: self.prop("application.process.binary"),
+            icon_name: self.prop("application.icon-name"),
+            process_id: self
+                .props
+                .get("application.process.id")
+                .and_then(|id| id.parse().ok()),
+        }
+    }
+
+    fn sink(&self, id: u32) -> Sink {
+        let name = self.name();
+        Sink {
+            description: self.prop("node.description"),
+            index: id,
+            volume: self.volume().round() as u32,
+            volumes: self.volumes(),
+            muted: self.muted || self.volume() == 0.0,
+            // the monitor of a sink is the sink itself in PipeWire
+            monitor_index: id,
+            monitor_name: format!("{name}.monitor"),
+            name,
+            state: self.state,
+            active_port: None,
+            ports: Vec::new(),
+            device: self.device(),
+        }
+    }
+
+    fn source(&self, id: u32) -> Source {
+        Source {
+            name: self.name(),
+            description: self.prop("node.description"),
+            index: id,
+            volume: self.volume().round() as u32,
+            volumes: self.volumes(),
+            muted: self.muted || self.volume() == 0.0,
+            monitor_index: None,
+            monitor_name: None,
+            state: self.state,
+            active_port: None,
+            ports: Vec::new(),
+            device: self.device(),
+        }
+    }
+}
+
+impl Graph {
+    /// Node a stream plays to, or records from, u32::MAX like pulseaudio if it isn't linked
+    fn linked(&self, id: u32, playing: bool) -> u32 {
+        self.links
+            .values()
+            .find_map(|&(output, input)| {
+                if playing {
+                    (output == id).then_some(input)
+                } else {
+                    (input == id).then_some(output)
+                }
+            })
+            .unwrap_or(u32::MAX)
+    }
+
+    /// The sink or source with the node name `name`, from the default metadata
+    fn named(&self, class: Class, name: Option<&str>) -> Option<(u32, &Audio)> {
+        let name = name?;
+        self.nodes
+            .iter()
+            .find(|(_, audio)| {
+                audio.class == class
+                    && audio.props.get("node.name").map(String::as_str) == Some(name)
+            })
+            .map(|(id, audio)| (*id, audio))
+    }
+
+    fn information(&self) -> Information {
+        let mut info = Information::default();
+        for (&id, audio) in &self.nodes {
+            match audio.class {
+                Class::Sink => {
+                    info.sinks.insert(audio.sink(id));
+                }
+                Class::Source => {
+                    info.sources.insert(audio.source(id));
+                }
+                Class::SinkInput => {
+                    info.sink_inputs.insert(SinkInput {
+                        name: audio.prop("media.name"),
+                        index: id,
+                        application: audio.application(),
+                        volume: audio.volume().round() as u32,
+                        muted: audio.muted,
+                        corked: !matches!(audio.state, State::Running),
+                        sink: self.linked(id, true),
+                    });
+                }
+                Class::SourceOutput => {
+                    info.source_outputs.insert(SourceOutput {
+                        name: audio.prop("media.name"),
+                        index: id,
+                        application: audio.application(),
+                        volume: audio.volume().round() as u32,
+                        muted: audio.muted,
+                        corked: !matches!(audio.state, State::Running),
+                        // pipewire-pulse sets it for pulseaudio's peak detect streams
+                        peak_detect: audio.props.get("resample.peaks").map(String::as_str)
+                            == Some("true"),
+                        source: self.linked(id, false),
+                    });
+                }
+            }
+        }
+        info.default_sink = self
+            .named(Class::Sink, self.default_sink.as_deref())
+            .map(|(id, audio)| audio.sink(id));
+        info.default_source = self
+            .named(Class::Source, self.default_source.as_deref())
+            .map(|(id, audio)| audio.source(id));
+        info
+    }
+}
+
+fn output(graph: &RefCell<Graph>) {
+    if let Ok(graph) = graph.try_borrow() {
+        crate::print(NAME, &Some(graph.information()));
+    }
+}
+
+fn props(dict: &DictRef) -> HashMap<String, String> {
+    dict.iter()
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect()
+}
+
+/// Takes the channel volumes, channel map, mute and base volume out of a Props param
+fn update(audio: &mut Audio, param: &Pod) {
+    let Ok((_, Value::Object(object))) = PodDeserializer::deserialize_any_from(param.as_bytes())
+    else {
+        return;
+    };
+    for property in object.properties {
+        match (property.key, property.value) {
+            (spa::sys::SPA_PROP_channelVolumes, Value::ValueArray(ValueArray::Float(volumes))) => {
+                audio.volumes = volumes
+            }
+            (spa::sys::SPA_PROP_channelMap, Value::ValueArray(ValueArray::Id(positions))) => {
+                audio.positions = positions.into_iter().map(|id| id.0).collect()
+            }
+            (spa::sys::SPA_PROP_mute, Value::Bool(muted)) => audio.muted = muted,
+            (spa::sys::SPA_PROP_volumeBase, Value::Float(base)) => audio.base_volume = Some(base),
+            _ => {}
+        }
+    }
+}
+
+/// Proxies and listeners of bound objects, they stop when they're dropped
+#[derive(Default)]
+struct Bound {
+    nodes: HashMap<u32, (Node, NodeListener)>,
+    metadata: Option<(Metadata, MetadataListener)>,
+}
+
+fn bind_node(
+    registry: &pw::registry::Registry,
+    global: &GlobalObject<&DictRef>,
+    graph: &Rc<RefCell<Graph>>,
+) -> Result<(Node, NodeListener)> {
+    let node: Node = registry.bind(global)?;
+    let id = global.id;
+    let (ginfo, gparam) = (Rc::clone(graph), Rc::clone(graph));
+    let listener = node
+        .add_listener_local()
+        .info(move |info| {
+            if let Some(audio) = ginfo.borrow_mut().nodes.get_mut(&id) {
+                if let Some(dict) = info.props() {
+                    audio.props = props(dict);
+                }
+                audio.state = match info.state() {
+                    NodeState::Running => State::Running,
+                    NodeState::Idle => State::Idle,
+                    NodeState::Suspended => State::Suspended,
+                    NodeState::Creating | NodeState::Error(_) => State::Invalid,
+                };
+            }
+            output(&ginfo);
+        })
+        .param(move |_, kind, _, _, param| {
+            let Some(param) = param else { return };
+            if kind != ParamType::Props {
+                return;
+            }
+            if let Some(audio) = gparam.borrow_mut().nodes.get_mut(&id) {
+                update(audio, param);
+            }
+            output(&gparam);
+        })
+        .register();
+    // the volume is sent now and whenever it changes
+    node.subscribe_params(&[ParamType::Props]);
+    Ok((node, listener))
+}
+
+fn bind_metadata(
+    registry: &pw::registry::Registry,
+    global: &GlobalObject<&DictRef>,
+    graph: &Rc<RefCell<Graph>>,
+) -> Result<(Metadata, MetadataListener)> {
+    let metadata: Metadata = registry.bind(global)?;
+    let graph = Rc::clone(graph);
+    let listener = metadata
+        .add_listener_local()
+        .property(move |_, key, _, value| {
+            let name = value
+                .and_then(|value| serde_json::from_str::<DefaultNode>(value).ok())
+                .map(|default| default.name);
+            match key {
+                Some("default.audio.sink") => graph.borrow_mut().default_sink = name,
+                Some("default.audio.source") => graph.borrow_mut().default_source = name,
+                // every key was removed
+                None => {
+                    let mut graph = graph.borrow_mut();
+                    graph.default_sink = None;
+                    graph.default_source = None;
+                }
+                _ => return 0,
+            }
+            output(&graph);
+            0
+        })
+        .register();
+    Ok((metadata, listener))
+}
+
+/// Prints the sinks, sources and streams whenever one changes, until PipeWire goes away
+pub(super) fn start() -> Result<()> {
+    let main_loop = MainLoop::new(None)?;
+    let context = Context::new(&main_loop)?;
+    let core = context.connect(None)?;
+    let registry = Rc::new(core.get_registry()?);
+    let graph = Rc::new(RefCell::new(Graph::default()));
+    let bound = Rc::new(RefCell::new(Bound::default()));
+    let lost = Rc::new(Cell::new(false));
+
+    let (weak_loop, lclone) = (main_loop.downgrade(), Rc::clone(&lost));
+    let _core_listener = core
+        .add_listener_local()
+        .error(move |id, _, res, message| {
+            // errors of other objects are about proxies of nodes which are already gone
+            if id == pw::core::PW_ID_CORE {
+                log::warn!("pipewire error {res}: {message}");
+                lclone.set(true);
+                if let Some(main_loop) = weak_loop.upgrade() {
+                    main_loop.quit();
+                }
+            }
+        })
+        .register();
+
+    let (rclone, gclone, bclone) = (Rc::clone(&registry), Rc::clone(&graph), Rc::clone(&bound));
+    let (gremove, bremove) = (Rc::clone(&graph), Rc::clone(&bound));
+    let _registry_listener = registry
+        .add_listener_local()
+        .global(move |global| {
+            let Some(dict) = global.props else { return };
+            match global.type_ {
+                ObjectType::Node => {
+                    let Some(class) = dict.get("media.class").and_then(Class::new) else {
+                        return;
+                    };
+                    gclone.borrow_mut().nodes.insert(
+                        global.id,
+                        Audio {
+                            class,
+                            props: props(dict),
+                            state: State::Invalid,
+                            volumes: Vec::new(),
+                            positions: Vec::new(),
+                            muted: false,
+                            base_volume: None,
+                        },
+                    );
+                    match bind_node(&rclone, global, &gclone) {
+                        Ok(node) => {
+                            bclone.borrow_mut().nodes.insert(global.id, node);
+                        }
+                        Err(e) => log::warn!("Failed to bind node {}: {e:#}", global.id),
+                    }
+                }
+                ObjectType::Link => {
+                    let node = |key: &str| dict.get(key).and_then(|id| id.parse().ok());
+                    if let (Some(output), Some(input)) =
+                        (node("link.output.node"), node("link.input.node"))
+                    {
+                        gclone.borrow_mut().links.insert(global.id, (output, input));
+                    }
+                }
+                // the settings metadata has no defaults
+                ObjectType::Metadata if dict.get("metadata.name") == Some("default") => {
+                    match bind_metadata(&rclone, global, &gclone) {
+                        Ok(metadata) => bclone.borrow_mut().metadata = Some(metadata),
+                        Err(e) => log::warn!("Failed to bind the default metadata: {e:#}"),
+                    }
+                }
+                _ => return,
+            }
+            output(&gclone);
+        })
+        .global_remove(move |id| {
+            bremove.borrow_mut().nodes.remove(&id);
+            {
+                let mut graph = gremove.borrow_mut();
+                if graph.nodes.remove(&id).is_none() && graph.links.remove(&id).is_none() {
+                    return;
+                }
+            }
+            output(&gremove);
+        })
+        .register();
+
+    main_loop.run();
+    if lost.get() {
+        bail!("Lost the connection to pipewire");
+    }
+    bail!("pipewire mainloop quit")
+}
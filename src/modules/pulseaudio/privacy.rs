@@ -0,0 +1,132 @@
+//! Applications recording from a microphone or using a camera, for an indicator in the bar.
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use serde::Serialize;
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use super::{Application, Information};
+
+pub const NAME: &str = "pulseaudio-privacy";
+
+#[derive(Serialize)]
+pub(super) struct Privacy<'a> {
+    /// applications with a stream recording from a source which isn't a monitor
+    microphone: Vec<&'a Application>,
+    /// processes with a video device open
+    camera: &'a [Application],
+}
+
+impl<'a> Privacy<'a> {
+    pub(super) fn new(info: &'a Information, cameras: &'a [Application]) -> Self {
+        let mut microphone: Vec<&Application> = Vec::new();
+        for output in &info.source_outputs {
+            // peak detect streams only measure the level, like the meters of pavucontrol
+            if output.corked || output.peak_detect {
+                continue;
+            }
+            let monitor = info
+                .sources
+                .iter()
+                .find(|source| source.index == output.source)
+                .is_some_and(|source| source.monitor_index.is_some());
+            if !monitor && !microphone.contains(&&output.application) {
+                microphone.push(&output.application);
+            }
+        }
+        Self {
+            microphone,
+            camera: cameras,
+        }
+    }
+}
+
+/// Processes with a `/dev/video*` device open. Only processes of the same user can be seen.
+fn cameras() -> Vec<Application> {
+    let Ok(processes) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let mut cameras = Vec::new();
+    for process in processes.flatten() {
+        let Some(pid) = process
+            .file_name()
+            .to_str()
+            .and_then(|pid| pid.parse().ok())
+        else {
+            continue;
+        };
+        let Ok(fds) = std::fs::read_dir(process.path().join("fd")) else {
+            continue;
+        };
+        let uses_camera = fds
+            .flatten()
+            .any(|fd| std::fs::read_link(fd.path()).is_ok_and(|target| is_camera(&target)));
+        if uses_camera {
+            let binary = std::fs::read_to_string(process.path().join("comm"))
+                .ok()
+                .map(|comm| comm.trim_end().to_owned());
+            cameras.push(Application {
+                name: None,
+                binary,
+                icon_name: None,
+                process_id: Some(pid),
+            });
+        }
+    }
+    cameras
+}
+
+fn is_camera(path: &Path) -> bool {
+    path.parent() == Some(Path::new("/dev"))
+        && path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("video"))
+}
+
+/// Keeps `cameras` up to date with the processes using a video device and calls `changed` whenever
+/// one is opened or closed, until `changed` returns false
+pub(super) fn watch_cameras(
+    cameras: Arc<Mutex<Vec<Application>>>,
+    changed: impl Fn() -> bool + Send + 'static,
+) -> anyhow::Result<()> {
+    let notifier = Inotify::init(InitFlags::empty())?;
+    // a camera the user can't open (EACCES) can't be used by their applications either
+    let watch = |notifier: &Inotify, path: &Path| {
+        let device = AddWatchFlags::IN_OPEN | AddWatchFlags::IN_CLOSE;
+        if let Err(e) = notifier.add_watch(path, device) {
+            log::warn!("Failed to watch {path:?}: {e}");
+        }
+    };
+    // cameras plugged in later are watched once they show up
+    notifier.add_watch("/dev", AddWatchFlags::IN_CREATE)?;
+    for entry in std::fs::read_dir("/dev")?.flatten() {
+        if is_camera(&entry.path()) {
+            watch(&notifier, &entry.path());
+        }
+    }
+    *cameras.lock().unwrap() = self::cameras();
+    std::thread::spawn(move || loop {
+        let events = match notifier.read_events() {
+            Ok(events) => events,
+            Err(e) => {
+                log::warn!("Stopped watching cameras: {e}");
+                return;
+            }
+        };
+        for event in events {
+            let Some(path) = event.name.map(|name| Path::new("/dev").join(name)) else {
+                continue;
+            };
+            if is_camera(&path) {
+                watch(&notifier, &path);
+            }
+        }
+        *cameras.lock().unwrap() = self::cameras();
+        if !changed() {
+            return;
+        }
+    });
+    Ok(())
+}
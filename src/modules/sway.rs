@@ -6,6 +6,8 @@ use sway::{Connection, EventType, NodeType};
 
 use crate::Module;
 
+pub const NAME: &str = "sway";
+
 #[derive(Debug, Serialize)]
 struct Data {
     kbd_layout: Option<String>,
@@ -111,7 +113,7 @@ impl Module for Sway {
         let mut conn = Connection::new();
         while let Err(..) = conn {
             conn = Connection::new();
-            crate::print(&None::<Data>);
+            crate::print(NAME, &None::<Data>);
             sleep(Duration::new(timeout, 0));
         }
         Ok(conn?)
@@ -120,8 +122,8 @@ impl Module for Sway {
     fn output(&self, conn: &mut Self::Connection) {
         let data = Data::get(conn);
         match data {
-            Ok(data) => crate::print(&Some(&data)),
-            Err(_) => crate::print::<Data>(&None),
+            Ok(data) => crate::print(NAME, &Some(&data)),
+            Err(_) => crate::print::<Data>(NAME, &None),
         }
     }
 
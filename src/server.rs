@@ -0,0 +1,254 @@
+//! Small http server for web based bars and dashboards.
+//! `GET /modules/<name>` returns the last output of the module, with `Accept: text/event-stream`
+//! every output is streamed as Server-Sent Event and with a websocket upgrade as text message.
+use anyhow::Result;
+use log::{debug, info, warn};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tungstenite::{handshake::derive_accept_key, protocol::Role, Message, WebSocket};
+
+use crate::hub::{self, Sink};
+
+/// `[serve]` section of the config
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServeConfig {
+    /// web pages allowed to read module outputs, e.g. `http://localhost:8080`.
+    /// Requests from other pages are refused, requests without `Origin` aren't from a browser
+    pub origins: Vec<String>,
+}
+
+/// how often a websocket is checked for pings and close frames while no output arrives
+const POLL: Duration = Duration::from_millis(100);
+
+/// streaming clients, with the module they are listening to
+type Clients = Arc<Mutex<Vec<(String, Sender<Value>)>>>;
+
+struct Server {
+    clients: Clients,
+}
+
+impl Sink for Server {
+    fn publish(&mut self, module: &str, output: &Value) {
+        // client threads exit when the connection is gone, drop them here
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|(name, tx)| name != module || tx.send(output.clone()).is_ok());
+    }
+}
+
+/// Binds to `addr` and serves module outputs from a background thread
+pub fn start(addr: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!("Serving modules on http://{addr}/modules/<name>");
+    let clients = Clients::default();
+    hub::add_sink(Server {
+        clients: Arc::clone(&clients),
+    });
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Failed to accept connection: {e}");
+                    continue;
+                }
+            };
+            let clients = Arc::clone(&clients);
+            std::thread::spawn(move || {
+                if let Err(e) = handle(stream, &clients) {
+                    debug!("Connection closed: {e}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Parsed request line and the headers we care about
+struct Request {
+    method: String,
+    path: String,
+    event_stream: bool,
+    websocket_key: Option<String>,
+    /// page the request comes from, if it comes from a browser
+    origin: Option<String>,
+}
+
+fn read_request(stream: &TcpStream) -> Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_owned();
+    let path = parts.next().unwrap_or_default().to_owned();
+    let mut request = Request {
+        method,
+        path,
+        event_stream: false,
+        websocket_key: None,
+        origin: None,
+    };
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "accept" => request.event_stream = value.contains("text/event-stream"),
+            "sec-websocket-key" => request.websocket_key = Some(value.to_owned()),
+            "origin" => request.origin = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+    Ok(request)
+}
+
+/// Whether a request from the page `origin` may read module outputs
+fn allowed(origin: Option<&str>, origins: &[String]) -> bool {
+    origin.is_none_or(|origin| origins.iter().any(|allowed| allowed == origin))
+}
+
+/// CORS headers for an allowed `origin`, pages only get to read outputs of their own request
+fn cors(origin: Option<&str>) -> String {
+    origin.map_or_else(String::new, |origin| {
+        format!("Access-Control-Allow-Origin: {origin}\r\nVary: Origin\r\n")
+    })
+}
+
+fn respond(
+    mut stream: TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+    origin: Option<&str>,
+) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{body}",
+        body.len(),
+        cors(origin)
+    )?;
+    Ok(())
+}
+
+fn would_block(e: &tungstenite::Error) -> bool {
+    matches!(e, tungstenite::Error::Io(e) if e.kind() == ErrorKind::WouldBlock)
+}
+
+/// Sends every output as text message, while answering pings and close frames of the client
+fn websocket(stream: TcpStream, rx: Receiver<Value>) -> Result<()> {
+    stream.set_nonblocking(true)?;
+    let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+    loop {
+        // tungstenite queues the pongs and the close reply, they go out with the next flush
+        loop {
+            match socket.read() {
+                Ok(_) => {}
+                Err(e) if would_block(&e) => break,
+                Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let sent = match rx.recv_timeout(POLL) {
+            Ok(output) => socket.send(Message::text(output.to_string())),
+            Err(RecvTimeoutError::Timeout) => socket.flush(),
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+        match sent {
+            // the rest is written by the next flush
+            Err(e) if would_block(&e) => {}
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            result => result?,
+        }
+    }
+}
+
+/// Registers a streaming client, the current state is the first thing it receives
+fn subscribe(clients: &Clients, module: &str) -> Receiver<Value> {
+    let (tx, rx) = channel();
+    hub::with_snapshot(module, |snapshot| {
+        if let Some(output) = snapshot {
+            let _ = tx.send(output.clone());
+        }
+        clients.lock().unwrap().push((module.to_owned(), tx));
+    });
+    rx
+}
+
+fn handle(mut stream: TcpStream, clients: &Clients) -> Result<()> {
+    let request = read_request(&stream)?;
+    let origin = request.origin.as_deref();
+    // websockets aren't covered by CORS, and other pages have no business reading outputs either
+    if !allowed(origin, &crate::config::get().serve.origins) {
+        debug!("Refusing request from {origin:?}");
+        return respond(stream, "403 Forbidden", "text/plain", "Forbidden", None);
+    }
+    let module = match request.path.strip_prefix("/modules/") {
+        Some(module) if request.method == "GET" && !module.is_empty() => module.to_owned(),
+        _ => return respond(stream, "404 Not Found", "text/plain", "Not Found", origin),
+    };
+    if let Some(key) = &request.websocket_key {
+        write!(
+            stream,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            derive_accept_key(key.as_bytes())
+        )?;
+        websocket(stream, subscribe(clients, &module))?;
+    } else if request.event_stream {
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n{}\r\n",
+            cors(origin)
+        )?;
+        for output in subscribe(clients, &module) {
+            write!(stream, "data: {output}\n\n")?;
+            stream.flush()?;
+        }
+    } else {
+        match hub::with_snapshot(&module, |snapshot| snapshot.map(Value::to_string)) {
+            Some(body) => respond(stream, "200 OK", "application/json", &body, origin)?,
+            None => respond(
+                stream,
+                "404 Not Found",
+                "text/plain",
+                "Unknown module",
+                origin,
+            )?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origins() {
+        let origins = vec![String::from("http://localhost:8080")];
+        assert!(allowed(None, &origins));
+        assert!(allowed(Some("http://localhost:8080"), &origins));
+        assert!(!allowed(Some("http://localhost:8081"), &origins));
+        assert!(!allowed(Some("https://example.com"), &[]));
+        assert_eq!(cors(None), "");
+        assert_eq!(
+            cors(Some("http://localhost:8080")),
+            "Access-Control-Allow-Origin: http://localhost:8080\r\nVary: Origin\r\n"
+        );
+    }
+}
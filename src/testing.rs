@@ -0,0 +1,27 @@
+//! Helpers shared by the tests of several modules
+
+/// Private bus, killed when dropped
+pub struct Bus(std::process::Child);
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Starts a private `dbus-daemon` and returns its address, `None` if there is none to start
+pub fn private_bus() -> Option<(Bus, String)> {
+    use std::io::BufRead;
+    let mut child = std::process::Command::new("dbus-daemon")
+        .args(["--session", "--nofork", "--print-address"])
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .ok()?;
+    let mut address = String::new();
+    let stdout = child.stdout.take()?;
+    std::io::BufReader::new(stdout)
+        .read_line(&mut address)
+        .ok()?;
+    Some((Bus(child), address.trim().to_owned()))
+}